@@ -30,7 +30,7 @@ class CommandMaker:
         return f'{NODE} generate_keys --filename {filename}'
 
     @staticmethod
-    def run_primary(keys, committee, store, parameters, app_api, abci_api, debug=False):
+    def run_primary(keys, committee, store, parameters, app_api, abci_api, genesis_time, debug=False):
         print(store, keys)
         assert isinstance(keys, str)
         assert isinstance(committee, str)
@@ -38,7 +38,8 @@ class CommandMaker:
         assert isinstance(debug, bool)
         v = '-vvv' if debug else '-vv'
         return (f'{NODE} {v} run --keys {keys} --committee {committee} '
-                f'--store {store} --parameters {parameters} primary --app-api {app_api} --abci-api {abci_api} ')
+                f'--store {store} --parameters {parameters} primary --app-api {app_api} --abci-api {abci_api} '
+                f'--genesis-time {genesis_time}')
 
     @staticmethod
     def run_worker(keys, committee, store, parameters, id, debug=False):
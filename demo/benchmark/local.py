@@ -2,7 +2,7 @@
 import subprocess
 from math import ceil
 from os.path import basename, splitext
-from time import sleep
+from time import sleep, time
 
 from benchmark.commands import CommandMaker
 from benchmark.config import Key, LocalCommittee, NodeParameters, BenchParameters, ConfigError
@@ -122,6 +122,8 @@ class LocalBench:
 
 
             print("[+] Spinning up primaries")
+            # All the primaries must agree on the time of the first round.
+            genesis_time = int(time())
             # Run the primaries (except the faulty ones).
             for i, address in enumerate(committee.primary_addresses(self.faults)):
                 cmd = CommandMaker.run_primary(
@@ -131,6 +133,7 @@ class LocalBench:
                     PathMaker.parameters_file(),
                     app_api = committee.app_addresses(self.faults)[i],
                     abci_api = committee.rpc_addresses(self.faults)[i],
+                    genesis_time = genesis_time,
                     debug=debug
                 )
                 log_file = PathMaker.primary_log_file(i)
@@ -5,7 +5,7 @@ from fabric.exceptions import GroupException
 from paramiko import RSAKey
 from paramiko.ssh_exception import PasswordRequiredException, SSHException
 from os.path import basename, splitext
-from time import sleep
+from time import sleep, time
 from math import ceil
 from copy import deepcopy
 import subprocess
@@ -237,6 +237,8 @@ class Bench:
 
         # Run the primaries (except the faulty ones).
         Print.info('Booting primaries...')
+        # All the primaries must agree on the time of the first round.
+        genesis_time = int(time())
         for i, address in enumerate(committee.primary_addresses(faults)):
             host = Committee.ip(address)
             cmd = CommandMaker.run_primary(
@@ -244,6 +246,7 @@ class Bench:
                 PathMaker.committee_file(),
                 PathMaker.db_path(i),
                 PathMaker.parameters_file(),
+                genesis_time=genesis_time,
                 debug=debug
             )
             log_file = PathMaker.primary_log_file(i)
@@ -1,56 +1,105 @@
-use crate::{Consensus, Info, Mempool, Snapshot, State};
+use crate::{
+    versions::DEFAULT_STATE_RETENTION, ChainSpec, CheckState, Consensus, GasConfig, Info, MemoryDb,
+    Mempool, PersistentDb, Snapshot, State, StateVersions,
+};
 use foundry_evm::revm::{
-    db::{CacheDB, EmptyDB},
+    db::{CacheDB, DatabaseRef},
     AccountInfo,
 };
+use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 pub struct App<Db> {
-    pub mempool: Mempool,
+    pub mempool: Mempool<Db>,
     pub snapshot: Snapshot,
     pub consensus: Consensus<Db>,
     pub info: Info<Db>,
 }
 
-impl Default for App<CacheDB<EmptyDB>> {
+impl Default for App<CacheDB<MemoryDb>> {
     fn default() -> Self {
-        Self::new(false)
+        Self::new(
+            false,
+            GasConfig::default(),
+            ChainSpec::default(),
+            DEFAULT_STATE_RETENTION,
+        )
     }
 }
 
-impl App<CacheDB<EmptyDB>> {
-    pub fn new(demo: bool) -> Self {
+impl App<CacheDB<MemoryDb>> {
+    /// Creates an in-memory app, which keeps the states of the last `state_retention` heights
+    /// for historical queries.
+    pub fn new(
+        demo: bool,
+        gas_config: GasConfig,
+        chain_spec: ChainSpec,
+        state_retention: u64,
+    ) -> Self {
         let mut state = State {
-            db: CacheDB::new(EmptyDB()),
+            db: CacheDB::new(MemoryDb::default()),
             block_height: Default::default(),
             app_hash: Default::default(),
             env: Default::default(),
+            pending_block: Default::default(),
+            latest_block: Default::default(),
+            history: Default::default(),
+            gas_config: Default::default(),
+            chain_spec: Default::default(),
+            changes: Default::default(),
+            trie: Default::default(),
         };
+        state.set_gas_config(gas_config);
+        state.set_chain_spec(chain_spec);
 
         if demo {
-            // addr(pk = 78aaa1de82137f31ac551fd8e876a6930aadd51b28c25e8c3420100f8e51d5c6)
-            state.db.insert_account_info(
-                "0xAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
-                    .parse()
-                    .unwrap(),
-                AccountInfo {
-                    balance: ethers::utils::parse_ether(1.5).unwrap(),
-                    ..Default::default()
-                },
-            );
+            fund_demo_accounts(&mut state);
+        }
+
+        Self::from_state(state, state_retention)
+    }
+}
+
+impl App<CacheDB<PersistentDb>> {
+    /// Opens the on-disk state at `path`, resuming from its last committed height.
+    pub fn open(
+        path: impl AsRef<Path>,
+        demo: bool,
+        gas_config: GasConfig,
+        chain_spec: ChainSpec,
+        state_retention: u64,
+    ) -> eyre::Result<Self> {
+        let mut state = PersistentDb::open(path)?.load()?;
+        state.set_gas_config(gas_config);
+        state.set_chain_spec(chain_spec);
+
+        // the demo accounts are only funded on a fresh chain
+        if demo && state.block_height == 0 {
+            fund_demo_accounts(&mut state);
         }
 
+        Ok(Self::from_state(state, state_retention))
+    }
+}
+
+impl<Db: Clone> App<Db> {
+    fn from_state(state: State<Db>, state_retention: u64) -> Self {
         let committed_state = Arc::new(Mutex::new(state.clone()));
+        let check_state = Arc::new(Mutex::new(CheckState::new(state.clone())));
         let current_state = Arc::new(Mutex::new(state));
+        let versions = Arc::new(Mutex::new(StateVersions::new(state_retention)));
 
         let consensus = Consensus {
             committed_state: committed_state.clone(),
             current_state,
+            check_state: check_state.clone(),
+            versions: versions.clone(),
         };
-        let mempool = Mempool::default();
+        let mempool = Mempool { state: check_state };
         let info = Info {
             state: committed_state,
+            versions,
         };
         let snapshot = Snapshot::default();
 
@@ -62,3 +111,16 @@ impl App<CacheDB<EmptyDB>> {
         }
     }
 }
+
+fn fund_demo_accounts<ExtDB: DatabaseRef>(state: &mut State<CacheDB<ExtDB>>) {
+    // addr(pk = ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80)
+    state.insert_account_info(
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+            .parse()
+            .unwrap(),
+        AccountInfo {
+            balance: ethers::utils::parse_ether(1.5).unwrap(),
+            ..Default::default()
+        },
+    );
+}
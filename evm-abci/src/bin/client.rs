@@ -1,12 +1,17 @@
 use ethers::prelude::*;
-use evm_abci::types::{Query, QueryResponse};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use evm_abci::{history::Receipt, types::{Query, QueryFailure, QueryResponse}};
 use eyre::Result;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use yansi::{Paint};
 
+// The demo app funds Alice's address at genesis
+static ALICE_WALLET: Lazy<LocalWallet> = Lazy::new(||{
+    "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".parse::<LocalWallet>().unwrap()
+});
 static ALICE: Lazy<Address> = Lazy::new(||{
-    "0xAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".parse::<Address>().unwrap()
+    ALICE_WALLET.address()
 });
 static BOB: Lazy<Address> = Lazy::new(||{
     "0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB".parse::<Address>().unwrap()
@@ -30,8 +35,8 @@ fn get_readable_eth_value(value: U256) -> Result<f64> {
     Ok(value_string.parse::<f64>()?)
 }
 
-async fn query_balance(host: &str, address: Address) -> Result<()> {
-    let query = Query::Balance(address);
+/// Sends the query to the host's `abci_query` endpoint, and deserializes its response.
+async fn query(host: &str, query: Query) -> Result<QueryResponse> {
     let query = serde_json::to_string(&query)?;
 
     let client = reqwest::Client::new();
@@ -42,8 +47,21 @@ async fn query_balance(host: &str, address: Address) -> Result<()> {
         .await?;
 
     let val = res.bytes().await?;
-    let val: QueryResponse = serde_json::from_slice(&val)?;
-    let val = val.as_balance();
+    if let Ok(failure) = serde_json::from_slice::<QueryFailure>(&val) {
+        eyre::bail!("query failed: {}", failure.message);
+    }
+    Ok(serde_json::from_slice(&val)?)
+}
+
+fn unexpected(res: QueryResponse) -> eyre::Report {
+    eyre::eyre!("unexpected query response: {:?}", res)
+}
+
+async fn query_balance(host: &str, address: Address) -> Result<()> {
+    let val = match query(host, Query::Balance(address)).await? {
+        QueryResponse::Balance(balance) => balance,
+        res => return Err(unexpected(res)),
+    };
     let readable_value = get_readable_eth_value(val)?;
     let name = ADDRESS_TO_NAME.get(&address).unwrap();
     println!(
@@ -67,8 +85,23 @@ async fn query_all_balances(host: &str) -> Result<()> {
     Ok(())
 }
 
-async fn send_transaction(host: &str, from: Address, to: Address, value: U256) -> Result<()> {
-    let from_name = ADDRESS_TO_NAME.get(&from).unwrap();
+/// Polls the host until one of the transactions gets included in a block.
+async fn wait_for_any_receipt(host: &str, hashes: &[H256]) -> Result<Receipt> {
+    for _ in 0..100 {
+        for hash in hashes {
+            match query(host, Query::Receipt(*hash)).await? {
+                QueryResponse::Receipt(Some(receipt)) => return Ok(receipt),
+                QueryResponse::Receipt(None) => {}
+                res => return Err(unexpected(res)),
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+    eyre::bail!("no transaction got included on {}", host)
+}
+
+async fn send_transaction(host: &str, from: &LocalWallet, to: Address, value: U256) -> Result<H256> {
+    let from_name = ADDRESS_TO_NAME.get(&from.address()).unwrap();
     let to_name = ADDRESS_TO_NAME.get(&to).unwrap();
     let readable_value = get_readable_eth_value(value)?;
     println!(
@@ -79,13 +112,38 @@ async fn send_transaction(host: &str, from: Address, to: Address, value: U256) -
         Paint::red(to_name).bold()
     );
 
+    let chain_id = match query(host, Query::ChainId).await? {
+        QueryResponse::ChainId(chain_id) => chain_id,
+        res => return Err(unexpected(res)),
+    };
+    // leave some room for the base fee to go up before the tx gets executed
+    let gas_price = match query(host, Query::BaseFee).await? {
+        QueryResponse::BaseFee(base_fee) => base_fee * 2,
+        res => return Err(unexpected(res)),
+    };
+    // the sender's next nonce in the committed state
+    let nonce = match query(host, Query::Nonce(from.address())).await? {
+        QueryResponse::Nonce(nonce) => nonce,
+        res => return Err(unexpected(res)),
+    };
     let tx = TransactionRequest::new()
-        .from(from)
+        .from(from.address())
         .to(to)
-        .value(value)
-        .gas(21000);
-
-    let tx = serde_json::to_string(&tx)?;
+        .value(value);
+    let gas = match query(host, Query::EstimateGas(tx.clone())).await? {
+        QueryResponse::Gas(gas) => gas,
+        res => return Err(unexpected(res)),
+    };
+    let tx: TypedTransaction = tx
+        .gas(gas)
+        .gas_price(gas_price)
+        .nonce(nonce)
+        .chain_id(chain_id)
+        .into();
+    let signature = from.sign_transaction_sync(&tx);
+    let raw = tx.rlp_signed(&signature);
+    let hash = H256(ethers::utils::keccak256(&raw));
+    let tx = format!("0x{}", hex::encode(raw));
 
     let client = reqwest::Client::new();
     client
@@ -94,7 +152,7 @@ async fn send_transaction(host: &str, from: Address, to: Address, value: U256) -
         .send()
         .await?;
 
-    Ok(())
+    Ok(hash)
 }
 
 #[tokio::main]
@@ -117,14 +175,24 @@ async fn main() -> Result<()> {
         Paint::new("Alice").bold(),
         Paint::red(format!("conflicting")).bold()
     );
-    send_transaction(host_2, *ALICE, *BOB, value).await?;
-    send_transaction(host_3, *ALICE, *CHARLIE, value).await?;
+    let hashes = [
+        send_transaction(host_2, &ALICE_WALLET, *BOB, value).await?,
+        send_transaction(host_3, &ALICE_WALLET, *CHARLIE, value).await?,
+    ];
 
     println!("---");
 
     println!("Waiting for consensus...");
-    // Takes ~5 seconds to actually apply the state transition?
-    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    // Only one of the conflicting transactions can get executed
+    for host in [host_2, host_3] {
+        let receipt = wait_for_any_receipt(host, &hashes).await?;
+        println!(
+            "{} included {:?} in block {}",
+            Paint::new(host).bold(),
+            receipt.transaction_hash,
+            receipt.block_number
+        );
+    }
 
     println!("---");
 
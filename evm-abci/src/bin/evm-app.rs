@@ -1,5 +1,7 @@
 use abci::async_api::Server;
-use evm_abci::App;
+use ethers::types::Address;
+use evm_abci::{App, ChainSpec, GasConfig, GenesisDb, Hardfork, Persist};
+use foundry_evm::revm::{Database, DatabaseCommit};
 use std::net::SocketAddr;
 
 use clap::Parser;
@@ -10,6 +12,29 @@ struct Args {
     host: String,
     #[clap(long, short)]
     demo: bool,
+    /// Persist the state in a RocksDB database at this path, instead of keeping it in memory
+    #[clap(long)]
+    db_path: Option<String>,
+    /// The maximum amount of gas the transactions of a block can use
+    #[clap(long, default_value_t = evm_abci::gas::DEFAULT_BLOCK_GAS_LIMIT)]
+    block_gas_limit: u64,
+    /// The base fee of the first block, in wei
+    #[clap(long, default_value_t = evm_abci::gas::INITIAL_BASE_FEE)]
+    initial_base_fee: u64,
+    /// The recipient of the priority fees, which must be the same on all the nodes. Defaults to
+    /// the author of each block's certificate.
+    #[clap(long)]
+    coinbase: Option<Address>,
+    #[clap(long, default_value_t = evm_abci::spec::DEFAULT_CHAIN_ID)]
+    chain_id: u64,
+    /// The hardforks to activate, as `<name>@<height>` (e.g. `--hardfork berlin --hardfork
+    /// london@100`). Defaults to revm's latest spec from genesis.
+    #[clap(long)]
+    hardfork: Vec<Hardfork>,
+    /// How many heights before the latest one can be queried. Each of them keeps the trie nodes
+    /// of the accounts written since. They are lost on restarts.
+    #[clap(long, default_value_t = evm_abci::versions::DEFAULT_STATE_RETENTION)]
+    state_retention: u64,
 }
 
 use tracing_error::ErrorLayer;
@@ -31,19 +56,46 @@ async fn main() -> eyre::Result<()> {
     let args = Args::parse();
     subscriber();
 
+    dbg!(&args.host);
+    // let addr = args.host.strip_prefix("http://").unwrap_or(&args.host);
+    let addr = args.host.parse::<SocketAddr>().unwrap();
+
+    // let addr = SocketAddr::new(addr, args.port);
+    let gas_config = GasConfig {
+        block_gas_limit: args.block_gas_limit,
+        initial_base_fee: args.initial_base_fee.into(),
+        coinbase: args.coinbase,
+    };
+    let chain_spec = ChainSpec::new(args.chain_id, &args.hardfork);
+    match args.db_path {
+        Some(path) => {
+            let app = App::open(
+                path,
+                args.demo,
+                gas_config,
+                chain_spec,
+                args.state_retention,
+            )?;
+            serve(app, addr).await
+        }
+        None => {
+            let app = App::new(args.demo, gas_config, chain_spec, args.state_retention);
+            serve(app, addr).await
+        }
+    }
+}
+
+async fn serve<Db>(app: App<Db>, addr: SocketAddr) -> eyre::Result<()>
+where
+    Db: Clone + Send + Sync + Database + DatabaseCommit + Persist + GenesisDb + 'static,
+{
     let App {
         consensus,
         mempool,
         info,
         snapshot,
-    } = App::new(args.demo);
+    } = app;
     let server = Server::new(consensus, mempool, info, snapshot);
-
-    dbg!(&args.host);
-    // let addr = args.host.strip_prefix("http://").unwrap_or(&args.host);
-    let addr = args.host.parse::<SocketAddr>().unwrap();
-
-    // let addr = SocketAddr::new(addr, args.port);
     server.run(addr).await?;
 
     Ok(())
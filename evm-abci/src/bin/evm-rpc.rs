@@ -0,0 +1,41 @@
+use evm_abci::EthRpc;
+use std::net::SocketAddr;
+
+use clap::Parser;
+
+#[derive(Debug, Clone, Parser)]
+struct Args {
+    /// The address to serve the Ethereum JSON-RPC on
+    #[clap(default_value = "0.0.0.0:8545")]
+    host: String,
+    /// The primary's ABCI API which the requests get forwarded to
+    #[clap(long, default_value = "http://127.0.0.1:3002")]
+    api: String,
+}
+
+use tracing_error::ErrorLayer;
+
+use tracing_subscriber::prelude::*;
+
+/// Initializes a tracing Subscriber for logging
+#[allow(dead_code)]
+pub fn subscriber() {
+    tracing_subscriber::Registry::default()
+        .with(tracing_subscriber::EnvFilter::new("evm-rpc=trace"))
+        .with(ErrorLayer::default())
+        .with(tracing_subscriber::fmt::layer())
+        .init()
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Args::parse();
+    subscriber();
+
+    let addr = args.host.parse::<SocketAddr>()?;
+    let rpc = EthRpc::new(args.api);
+
+    warp::serve(rpc.routes()).run(addr).await;
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use ethers::prelude::*;
+use foundry_evm::revm::Account as RevmAccount;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The accounts and storage slots written since the last commit, so that only they get updated
+/// in the state trie and persisted, instead of the whole state.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    accounts: BTreeMap<Address, AccountChanges>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountChanges {
+    /// The account's whole storage was wiped, e.g. by a self-destruct, so that only the slots
+    /// written afterwards can be non-zero
+    pub storage_cleared: bool,
+    pub slots: BTreeSet<U256>,
+}
+
+impl Changes {
+    /// Records the account as written, without any storage slot.
+    pub fn account(&mut self, address: Address) -> &mut AccountChanges {
+        self.accounts.entry(address).or_default()
+    }
+
+    pub fn slot(&mut self, address: Address, slot: U256) {
+        self.account(address).slots.insert(slot);
+    }
+
+    /// Records the changes of an executed transaction, as committed to the database. They may
+    /// include accounts and slots which were only read, which is harmless.
+    pub fn record<'a>(
+        &mut self,
+        changes: impl IntoIterator<Item = (&'a Address, &'a RevmAccount)>,
+    ) {
+        for (address, account) in changes {
+            let changes = self.account(*address);
+            if account.is_destroyed || account.storage_cleared {
+                changes.storage_cleared = true;
+                changes.slots.clear();
+            }
+            changes.slots.extend(account.storage.keys().copied());
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Address, &AccountChanges)> {
+        self.accounts.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foundry_evm::revm::AccountInfo;
+
+    #[test]
+    fn clearing_the_storage_drops_the_previous_slots() {
+        let alice = Address::random();
+        let mut changes = Changes::default();
+        changes.slot(alice, 1.into());
+
+        let mut account = RevmAccount::from(AccountInfo::default());
+        account.is_destroyed = true;
+        changes.record([(&alice, &account)]);
+        changes.slot(alice, 2.into());
+
+        let (address, account) = changes.iter().next().unwrap();
+        assert_eq!(*address, alice);
+        assert!(account.storage_cleared);
+        assert_eq!(account.slots, [U256::from(2)].into_iter().collect());
+    }
+}
@@ -0,0 +1,400 @@
+use crate::{
+    history::{History, KvStore, SealedBlock},
+    trie::{TrieNodes, EMPTY_ROOT},
+    Changes, State, StateTrie,
+};
+use ethers::prelude::*;
+use eyre::WrapErr;
+use foundry_evm::revm::{
+    db::{CacheDB, DatabaseRef, EmptyDB},
+    AccountInfo, Bytecode, KECCAK_EMPTY,
+};
+use rocksdb::{WriteBatch, DB};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+const CODE_PREFIX: u8 = b'c';
+const HEIGHT_KEY: &[u8] = b"m/height";
+const APP_HASH_KEY: &[u8] = b"m/app_hash";
+
+/// Databases which can durably store the state they hold at every commit.
+pub trait Persist {
+    /// Called with the committed state, the changes since the previous commit, the height and
+    /// the state trie, whose root is the app hash, on every ABCI `Commit`.
+    fn persist(&self, changes: &Changes, height: i64, trie: &StateTrie) -> eyre::Result<()>;
+
+    /// A database which reads the state at the root of `trie`, kept to query a past height.
+    fn version(&self, trie: &StateTrie) -> Self;
+}
+
+/// The backing store of the in-memory state's [`CacheDB`]. The latest state holds all its
+/// accounts in its cache, so it reads nothing from here, but its versions start with an empty
+/// cache and read the accounts from the state trie at their root, and the code of the
+/// contracts from a map shared by all of them, instead of copying the latest state's cache.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryDb {
+    /// The trie at the version's root, none for the latest state
+    trie: Option<StateTrie>,
+    code: Arc<RwLock<HashMap<H256, Bytecode>>>,
+}
+
+/// The in-memory database keeps nothing across restarts.
+impl Persist for CacheDB<MemoryDb> {
+    fn persist(&self, _changes: &Changes, _height: i64, _trie: &StateTrie) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// The cache only ever gets new contracts, and the shared map only gets them from the
+    /// latest state, so they are only copied over when the cache has more of them.
+    fn version(&self, trie: &StateTrie) -> Self {
+        let mut code = self.db.code.write().expect("poisoned lock");
+        if code.len() < self.contracts.len() {
+            for (hash, contract) in &self.contracts {
+                code.entry(*hash).or_insert_with(|| contract.clone());
+            }
+        }
+        CacheDB::new(MemoryDb {
+            trie: Some(trie.clone()),
+            code: self.db.code.clone(),
+        })
+    }
+}
+
+/// The trie nodes of a version are kept as long as it is, so its reads cannot fail.
+impl DatabaseRef for MemoryDb {
+    fn basic(&self, address: H160) -> AccountInfo {
+        let trie = match &self.trie {
+            Some(trie) => trie,
+            None => return EmptyDB().basic(address),
+        };
+        let account = trie
+            .account(address)
+            .expect("could not read account from the state trie");
+        AccountInfo {
+            balance: account.balance,
+            nonce: account.nonce.as_u64(),
+            code_hash: account.code_hash,
+            code: None,
+        }
+    }
+
+    fn code_by_hash(&self, code_hash: H256) -> Bytecode {
+        match self.code.read().expect("poisoned lock").get(&code_hash) {
+            Some(code) => code.clone(),
+            None => EmptyDB().code_by_hash(code_hash),
+        }
+    }
+
+    fn storage(&self, address: H160, index: U256) -> U256 {
+        match &self.trie {
+            Some(trie) => trie
+                .storage(address, index)
+                .expect("could not read storage from the state trie"),
+            None => EmptyDB().storage(address, index),
+        }
+    }
+
+    fn block_hash(&self, number: U256) -> H256 {
+        EmptyDB().block_hash(number)
+    }
+}
+
+/// A RocksDB-backed state database, used as the backing store of a [`CacheDB`] which buffers
+/// all the writes of a block until they get persisted at `Commit`.
+///
+/// The accounts and storage slots are stored as the nodes of the state trie, whose root is the
+/// app hash, so that only the nodes of the accounts written during a block need to be written
+/// at its commit. Nothing is loaded on startup: accounts are read from the trie at the last
+/// committed root the first time they are used, and cached from then on. The nodes which are
+/// not part of the latest trie get deleted once the retained states do not need them anymore.
+#[derive(Clone)]
+pub struct PersistentDb {
+    db: Arc<DB>,
+    /// The trie at the last committed root, shared by the clones of the latest state. It stays
+    /// right for all of them, since the accounts written since they got cloned are in their
+    /// cache, and moving it along with the commits lets the nodes of the earlier roots go.
+    trie: Arc<RwLock<StateTrie>>,
+}
+
+impl fmt::Debug for PersistentDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PersistentDb")
+            .field("path", &self.db.path())
+            .field("root", &self.trie().root())
+            .finish()
+    }
+}
+
+impl PersistentDb {
+    pub fn open(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let db = DB::open_default(path.as_ref())
+            .wrap_err(format!("could not open state db at {:?}", path.as_ref()))?;
+        let db = Arc::new(db);
+        // the app hash is the state root
+        let root = match db.get(APP_HASH_KEY)? {
+            Some(app_hash) if app_hash.len() == 32 => H256::from_slice(&app_hash),
+            _ => EMPTY_ROOT,
+        };
+        let trie = StateTrie::new(TrieNodes::persistent(db.clone())?, root);
+        Ok(Self {
+            db,
+            trie: Arc::new(RwLock::new(trie)),
+        })
+    }
+
+    fn trie(&self) -> StateTrie {
+        self.trie.read().expect("poisoned lock").clone()
+    }
+
+    /// The last committed state, whose accounts get loaded on demand.
+    pub fn load(&self) -> eyre::Result<State<CacheDB<PersistentDb>>> {
+        let block_height = match self.db.get(HEIGHT_KEY)? {
+            Some(height) => i64::from_be_bytes(
+                height
+                    .as_slice()
+                    .try_into()
+                    .wrap_err("corrupted block height")?,
+            ),
+            None => 0,
+        };
+        let app_hash = self.db.get(APP_HASH_KEY)?.unwrap_or_default();
+
+        let history = History::new(Arc::new(self.clone()));
+        // the transactions and receipts of the latest block are already stored in the history
+        let latest_block = SealedBlock {
+            block: history
+                .block_by_number(block_height as u64)?
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        Ok(State {
+            block_height,
+            app_hash,
+            db: CacheDB::new(self.clone()),
+            env: Default::default(),
+            pending_block: Default::default(),
+            latest_block,
+            history,
+            gas_config: Default::default(),
+            chain_spec: Default::default(),
+            changes: Default::default(),
+            trie: self.trie(),
+        })
+    }
+}
+
+/// A failed read cannot be reported to revm, and going on with a missing account would make the
+/// execution diverge from the other nodes', so the reads panic instead.
+impl DatabaseRef for PersistentDb {
+    fn basic(&self, address: H160) -> AccountInfo {
+        let account = self
+            .trie
+            .read()
+            .expect("poisoned lock")
+            .account(address)
+            .expect("could not read account from the state db");
+        AccountInfo {
+            balance: account.balance,
+            nonce: account.nonce.as_u64(),
+            code_hash: account.code_hash,
+            code: None,
+        }
+    }
+
+    fn code_by_hash(&self, code_hash: H256) -> Bytecode {
+        match self
+            .db
+            .get(code_key(code_hash))
+            .expect("could not read code from the state db")
+        {
+            Some(code) => Bytecode::new_raw(code.into()),
+            None => Bytecode::new(),
+        }
+    }
+
+    fn storage(&self, address: H160, index: U256) -> U256 {
+        self.trie
+            .read()
+            .expect("poisoned lock")
+            .storage(address, index)
+            .expect("could not read storage from the state db")
+    }
+
+    fn block_hash(&self, _number: U256) -> H256 {
+        H256::zero()
+    }
+}
+
+/// The chain history is stored next to the state, under its own key prefixes.
+impl KvStore for PersistentDb {
+    fn get(&self, key: &[u8]) -> eyre::Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn write(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> eyre::Result<()> {
+        let mut batch = WriteBatch::default();
+        for (key, value) in entries {
+            batch.put(key, value);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+/// Writes the trie nodes of the accounts and slots written since the last commit, and the code
+/// of the contracts deployed since then, in a single batch with the height and app hash. The
+/// nodes which got released since then are deleted in the same batch.
+impl Persist for CacheDB<PersistentDb> {
+    fn persist(&self, changes: &Changes, height: i64, trie: &StateTrie) -> eyre::Result<()> {
+        let mut batch = WriteBatch::default();
+
+        for (address, _) in changes.iter() {
+            let info = match self.accounts.get(address) {
+                Some(account) => &account.info,
+                None => continue,
+            };
+            if info.code_hash == KECCAK_EMPTY || info.code_hash.is_zero() {
+                continue;
+            }
+            let key = code_key(info.code_hash);
+            if self.db.db.get(&key)?.is_some() {
+                continue;
+            }
+            let code = match info
+                .code
+                .as_ref()
+                .or_else(|| self.contracts.get(&info.code_hash))
+            {
+                Some(code) => code,
+                None => continue,
+            };
+            batch.put(key, code.bytes().slice(..code.len()));
+        }
+
+        batch.put(HEIGHT_KEY, height.to_be_bytes());
+        batch.put(APP_HASH_KEY, trie.root().as_bytes());
+        trie.nodes().write(batch)?;
+        *self.db.trie.write().expect("poisoned lock") = trie.clone();
+        Ok(())
+    }
+
+    /// The versions read everything from the trie at their root instead of copying the cache.
+    fn version(&self, trie: &StateTrie) -> Self {
+        CacheDB::new(PersistentDb {
+            db: self.db.db.clone(),
+            trie: Arc::new(RwLock::new(trie.clone())),
+        })
+    }
+}
+
+fn code_key(code_hash: H256) -> Vec<u8> {
+    let mut key = vec![CODE_PREFIX];
+    key.extend_from_slice(code_hash.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persists_and_reloads_state() {
+        let path = std::env::temp_dir().join(format!("evm-abci-db-{:?}", Address::random()));
+        let alice = Address::random();
+        let bob = Address::random();
+        let code = Bytecode::new_raw(vec![0x60, 0x00].into());
+        let code_hash = H256(ethers::utils::keccak256([0x60, 0x00]));
+
+        let db = PersistentDb::open(&path).unwrap();
+        let mut state = db.load().unwrap();
+        state.insert_account_info(
+            alice,
+            AccountInfo {
+                balance: 10.into(),
+                nonce: 2,
+                ..Default::default()
+            },
+        );
+        state.insert_account_info(
+            bob,
+            AccountInfo {
+                nonce: 1,
+                code_hash,
+                code: Some(code.clone()),
+                ..Default::default()
+            },
+        );
+        state.insert_account_storage(bob, 1.into(), 5.into());
+        state.insert_account_storage(bob, 2.into(), 6.into());
+        state.state_root();
+        state.db.persist(&state.changes, 3, &state.trie).unwrap();
+        state.changes = Changes::default();
+        let first = state.trie.clone();
+
+        // only bob's account changed, and its zeroed slot gets deleted from the trie
+        state.insert_account_storage(bob, 2.into(), 0.into());
+        assert_eq!(state.changes.iter().count(), 1);
+        let root = state.state_root();
+        state.db.persist(&state.changes, 4, &state.trie).unwrap();
+
+        // the previous heights can still be read while their trie is open
+        let version = state.db.version(&first);
+        assert_eq!(version.db.storage(bob, 2.into()), 6.into());
+        drop((state, version, first));
+        drop(db);
+
+        let state = PersistentDb::open(&path).unwrap().load().unwrap();
+        assert_eq!(state.block_height, 4);
+        assert_eq!(state.app_hash, root.as_bytes());
+        assert_eq!(state.trie.root(), root);
+        // nothing is loaded until it is read, from the trie
+        assert!(state.db.accounts.is_empty());
+        let db = &state.db.db;
+        assert_eq!(db.basic(alice).nonce, 2);
+        assert_eq!(db.storage(bob, 1.into()), 5.into());
+        assert_eq!(db.storage(bob, 2.into()), U256::zero());
+        assert_eq!(db.basic(bob).code_hash, code_hash);
+        assert_eq!(db.code_by_hash(code_hash).bytes(), code.bytes());
+
+        drop(state);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn memory_versions_read_the_trie() {
+        let alice = Address::random();
+        let code = Bytecode::new_raw(vec![0x60, 0x00].into());
+        let code_hash = H256(ethers::utils::keccak256([0x60, 0x00]));
+
+        let mut state = State::default();
+        state.insert_account_info(
+            alice,
+            AccountInfo {
+                balance: 10.into(),
+                code_hash,
+                code: Some(code.clone()),
+                ..Default::default()
+            },
+        );
+        state.insert_account_storage(alice, 1.into(), 5.into());
+        state.state_root();
+
+        // nothing gets copied from the cache but the code
+        let version = state.version();
+        assert!(version.db.accounts.is_empty());
+        let db = &version.db.db;
+        assert_eq!(db.basic(alice).balance, 10.into());
+        assert_eq!(db.storage(alice, 1.into()), 5.into());
+        assert_eq!(db.code_by_hash(code_hash).bytes(), code.bytes());
+
+        // and the version stays at its root while the state moves on
+        state.insert_account_storage(alice, 1.into(), 6.into());
+        state.state_root();
+        assert_eq!(db.storage(alice, 1.into()), 5.into());
+    }
+}
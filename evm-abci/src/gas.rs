@@ -0,0 +1,101 @@
+use crate::history::Block;
+use ethers::prelude::*;
+
+/// The gas limit of a block, same as Ethereum mainnet's.
+pub const DEFAULT_BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+/// The base fee of the first block (1 gwei).
+pub const INITIAL_BASE_FEE: u64 = 1_000_000_000;
+
+/// Bounds the base fee change between two blocks to 1/8th (12.5%).
+const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+
+/// Blocks target half of their gas limit.
+const ELASTICITY_MULTIPLIER: u64 = 2;
+
+/// The gas parameters of the chain. They drive execution, so they must be the same on all the
+/// nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GasConfig {
+    /// The maximum amount of gas the transactions of a block can use
+    pub block_gas_limit: u64,
+    /// The base fee of the first block, which then adjusts to the blocks' usage
+    pub initial_base_fee: U256,
+    /// The recipient of the priority fees, or `None` to pay them to the author of the block's
+    /// certificate. The base fee is burnt.
+    pub coinbase: Option<Address>,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            block_gas_limit: DEFAULT_BLOCK_GAS_LIMIT,
+            initial_base_fee: INITIAL_BASE_FEE.into(),
+            coinbase: None,
+        }
+    }
+}
+
+impl GasConfig {
+    /// The base fee of the block built on top of `parent`, as specified by EIP-1559: it goes up
+    /// when the parent used more than half of its gas limit, and down when it used less.
+    pub fn next_base_fee(&self, parent: &Block) -> U256 {
+        // no block was built yet
+        if parent.number.is_zero() {
+            return self.initial_base_fee;
+        }
+
+        let base_fee = parent.base_fee_per_gas;
+        let target = parent.gas_limit / ELASTICITY_MULTIPLIER;
+        if target.is_zero() {
+            return base_fee;
+        }
+
+        match parent.gas_used.cmp(&target) {
+            std::cmp::Ordering::Equal => base_fee,
+            std::cmp::Ordering::Greater => {
+                let delta = base_fee * (parent.gas_used - target)
+                    / target
+                    / BASE_FEE_MAX_CHANGE_DENOMINATOR;
+                base_fee + std::cmp::max(delta, U256::one())
+            }
+            std::cmp::Ordering::Less => {
+                let delta = base_fee * (target - parent.gas_used)
+                    / target
+                    / BASE_FEE_MAX_CHANGE_DENOMINATOR;
+                base_fee.saturating_sub(delta)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent(gas_used: u64) -> Block {
+        Block {
+            number: 1.into(),
+            gas_limit: 100.into(),
+            gas_used: gas_used.into(),
+            base_fee_per_gas: 800.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn base_fee_follows_usage() {
+        let config = GasConfig::default();
+        assert_eq!(
+            config.next_base_fee(&Block::default()),
+            config.initial_base_fee
+        );
+
+        // on target
+        assert_eq!(config.next_base_fee(&parent(50)), 800.into());
+        // full blocks increase the base fee by 12.5%
+        assert_eq!(config.next_base_fee(&parent(100)), 900.into());
+        // empty blocks decrease it by 12.5%
+        assert_eq!(config.next_base_fee(&parent(0)), 700.into());
+    }
+}
@@ -0,0 +1,334 @@
+use crate::{
+    gas::GasConfig,
+    spec::{ChainSpec, Hardfork},
+    State,
+};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use foundry_evm::revm::{
+    db::{CacheDB, DatabaseRef},
+    AccountInfo, Bytecode, SpecId,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+
+/// The initial state of the chain, sent by the Engine in `InitChain`'s `app_state_bytes`.
+///
+/// The format is a subset of geth's and anvil's genesis files: the `alloc` section can be copied
+/// over as is, and quantities can be either numbers, decimal strings or hex strings.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Genesis {
+    #[serde(default)]
+    pub config: ChainConfig,
+    /// The UNIX timestamp of the genesis block. Block timestamps never go below it.
+    #[serde(default, deserialize_with = "quantity_opt")]
+    pub timestamp: Option<U256>,
+    /// The block gas limit
+    #[serde(default, deserialize_with = "quantity_opt")]
+    pub gas_limit: Option<U256>,
+    /// The base fee of the first block
+    #[serde(default, deserialize_with = "quantity_opt")]
+    pub base_fee_per_gas: Option<U256>,
+    /// The recipient of the priority fees
+    #[serde(default)]
+    pub coinbase: Option<Address>,
+    /// The accounts at genesis, keyed by address
+    #[serde(default, deserialize_with = "alloc")]
+    pub alloc: BTreeMap<Address, GenesisAccount>,
+}
+
+/// The `config` section of geth's genesis files: the chain id and the heights at which the
+/// hardforks get activated.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainConfig {
+    #[serde(default)]
+    pub chain_id: Option<u64>,
+    pub homestead_block: Option<u64>,
+    pub eip150_block: Option<u64>,
+    pub eip158_block: Option<u64>,
+    pub byzantium_block: Option<u64>,
+    pub constantinople_block: Option<u64>,
+    pub petersburg_block: Option<u64>,
+    pub istanbul_block: Option<u64>,
+    pub berlin_block: Option<u64>,
+    pub london_block: Option<u64>,
+    pub merge_netsplit_block: Option<u64>,
+}
+
+impl ChainConfig {
+    /// The scheduled hardforks, empty if none is configured.
+    pub fn hardforks(&self) -> Vec<Hardfork> {
+        [
+            (self.homestead_block, SpecId::HOMESTEAD),
+            (self.eip150_block, SpecId::TANGERINE),
+            (self.eip158_block, SpecId::SPURIOUS_DRAGON),
+            (self.byzantium_block, SpecId::BYZANTIUM),
+            (self.constantinople_block, SpecId::CONSTANTINOPLE),
+            (self.petersburg_block, SpecId::PETERSBURG),
+            (self.istanbul_block, SpecId::ISTANBUL),
+            (self.berlin_block, SpecId::BERLIN),
+            (self.london_block, SpecId::LONDON),
+            (self.merge_netsplit_block, SpecId::MERGE),
+        ]
+        .into_iter()
+        .filter_map(|(height, spec)| height.map(|height| Hardfork { spec, height }))
+        .collect()
+    }
+
+    /// Overrides the provided chain spec with the chain id and hardforks set in the genesis.
+    /// Hardforks get scheduled in order, so a later hardfork at the same height wins.
+    pub fn chain_spec(&self, spec: ChainSpec) -> ChainSpec {
+        let chain_id = self.chain_id.unwrap_or(spec.chain_id);
+        let hardforks = match self.hardforks() {
+            hardforks if hardforks.is_empty() => spec.hardforks(),
+            // geth's genesis starts at frontier
+            mut hardforks => {
+                hardforks.insert(
+                    0,
+                    Hardfork {
+                        spec: SpecId::FRONTIER,
+                        height: 0,
+                    },
+                );
+                hardforks
+            }
+        };
+        ChainSpec::new(chain_id, &hardforks)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GenesisAccount {
+    #[serde(default, deserialize_with = "quantity")]
+    pub balance: U256,
+    #[serde(default, deserialize_with = "quantity_opt")]
+    pub nonce: Option<U256>,
+    #[serde(default)]
+    pub code: Option<Bytes>,
+    #[serde(default, deserialize_with = "storage")]
+    pub storage: BTreeMap<U256, U256>,
+}
+
+impl Genesis {
+    pub fn from_json(json: &[u8]) -> eyre::Result<Self> {
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// Overrides the provided gas parameters with the ones set in the genesis.
+    pub fn gas_config(&self, mut config: GasConfig) -> eyre::Result<GasConfig> {
+        if let Some(gas_limit) = self.gas_limit {
+            if gas_limit > U256::from(u64::MAX) {
+                eyre::bail!("genesis gas limit {} does not fit in 64 bits", gas_limit);
+            }
+            config.block_gas_limit = gas_limit.as_u64();
+        }
+        if let Some(base_fee) = self.base_fee_per_gas {
+            config.initial_base_fee = base_fee;
+        }
+        if self.coinbase.is_some() {
+            config.coinbase = self.coinbase;
+        }
+        Ok(config)
+    }
+}
+
+/// Databases which the genesis accounts can be written to.
+pub trait GenesisDb {
+    fn insert_genesis_account(
+        &mut self,
+        address: Address,
+        account: &GenesisAccount,
+    ) -> eyre::Result<()>;
+}
+
+impl<ExtDB: DatabaseRef> GenesisDb for CacheDB<ExtDB> {
+    fn insert_genesis_account(
+        &mut self,
+        address: Address,
+        account: &GenesisAccount,
+    ) -> eyre::Result<()> {
+        let nonce = account.nonce.unwrap_or_default();
+        if nonce > U256::from(u64::MAX) {
+            eyre::bail!(
+                "genesis nonce {} of {:?} does not fit in 64 bits",
+                nonce,
+                address
+            );
+        }
+        let mut info = AccountInfo {
+            balance: account.balance,
+            nonce: nonce.as_u64(),
+            ..Default::default()
+        };
+        if let Some(code) = account.code.as_ref().filter(|code| !code.is_empty()) {
+            info.code_hash = H256(keccak256(code));
+            info.code = Some(Bytecode::new_raw(code.0.clone()));
+        }
+        self.insert_account_info(address, info);
+
+        for (slot, value) in &account.storage {
+            self.insert_account_storage(address, *slot, *value);
+        }
+        Ok(())
+    }
+}
+
+impl<Db> State<Db> {
+    /// Applies the chain parameters of the genesis. They are not persisted, so they need to be
+    /// applied again whenever the app restarts.
+    pub fn apply_chain_config(&mut self, genesis: &Genesis) -> eyre::Result<()> {
+        let gas_config = genesis.gas_config(self.gas_config.clone())?;
+        self.set_gas_config(gas_config);
+        self.set_chain_spec(genesis.config.chain_spec(self.chain_spec.clone()));
+        Ok(())
+    }
+}
+
+impl<Db: GenesisDb> State<Db> {
+    /// Applies the chain parameters and writes the genesis accounts.
+    pub fn apply_genesis(&mut self, genesis: &Genesis) -> eyre::Result<()> {
+        if let Some(timestamp) = genesis.timestamp {
+            if timestamp > U256::from(u64::MAX) {
+                eyre::bail!("genesis timestamp {} does not fit in 64 bits", timestamp);
+            }
+            self.latest_block.block.timestamp = timestamp.as_u64().into();
+        }
+        self.apply_chain_config(genesis)?;
+
+        for (address, account) in &genesis.alloc {
+            self.db.insert_genesis_account(*address, account)?;
+            self.changes.account(*address);
+            for slot in account.storage.keys() {
+                self.changes.slot(*address, *slot);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a JSON number, a decimal string or a `0x`-prefixed hex string.
+fn parse_quantity<E: de::Error>(value: serde_json::Value) -> Result<U256, E> {
+    match value {
+        serde_json::Value::Number(number) => number
+            .as_u64()
+            .map(U256::from)
+            .ok_or_else(|| E::custom(format!("invalid quantity {}", number))),
+        serde_json::Value::String(string) => match string.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).ok(),
+            None => U256::from_dec_str(&string).ok(),
+        }
+        .ok_or_else(|| E::custom(format!("invalid quantity {}", string))),
+        value => Err(E::custom(format!("invalid quantity {}", value))),
+    }
+}
+
+fn quantity<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+    parse_quantity(serde_json::Value::deserialize(deserializer)?)
+}
+
+fn quantity_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<U256>, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => Ok(None),
+        value => parse_quantity(value).map(Some),
+    }
+}
+
+/// Addresses are not always `0x`-prefixed in geth's genesis files.
+fn alloc<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<Address, GenesisAccount>, D::Error> {
+    BTreeMap::<String, GenesisAccount>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(address, account)| {
+            let address = address
+                .parse::<Address>()
+                .map_err(|_| de::Error::custom(format!("invalid address {}", address)))?;
+            Ok((address, account))
+        })
+        .collect()
+}
+
+/// Storage slots and values are hex strings, which are not always `0x`-prefixed nor padded to
+/// 32 bytes.
+fn storage<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<U256, U256>, D::Error> {
+    let parse = |word: &str| -> Result<U256, D::Error> {
+        U256::from_str_radix(word.trim_start_matches("0x"), 16)
+            .map_err(|_| de::Error::custom(format!("invalid storage word {}", word)))
+    };
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(slot, value)| Ok((parse(&slot)?, parse(&value)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foundry_evm::revm::Database;
+
+    #[test]
+    fn applies_geth_style_genesis() {
+        let genesis = br#"{
+            "config": { "chainId": 1337, "berlinBlock": 0, "londonBlock": 5 },
+            "timestamp": "0x5f5e100",
+            "gasLimit": "15000000",
+            "alloc": {
+                "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266": {
+                    "balance": "1000000000000000000"
+                },
+                "5FbDB2315678afecb367f032d93F642f64180aa3": {
+                    "balance": "0x0",
+                    "nonce": "0x1",
+                    "code": "0x6080",
+                    "storage": { "0x01": "2a" }
+                }
+            }
+        }"#;
+        let genesis = Genesis::from_json(genesis).unwrap();
+
+        let mut state = State::default();
+        state.apply_genesis(&genesis).unwrap();
+        assert_eq!(state.env.cfg.chain_id, 1337.into());
+        assert_eq!(state.env.cfg.spec_id, SpecId::BERLIN);
+        assert_eq!(state.chain_spec.spec_at(5), SpecId::LONDON);
+        assert_eq!(state.gas_config.block_gas_limit, 15_000_000);
+        assert_eq!(state.env.block.gas_limit, 15_000_000.into());
+        assert_eq!(state.latest_block.block.timestamp, 100_000_000.into());
+
+        let alice: Address = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            state.db.basic(alice).balance,
+            ethers::utils::parse_ether(1).unwrap()
+        );
+
+        let contract: Address = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+            .parse()
+            .unwrap();
+        let info = state.db.basic(contract);
+        assert_eq!(info.nonce, 1);
+        assert_eq!(info.code_hash, H256(keccak256([0x60, 0x80])));
+        assert_eq!(state.db.storage(contract, 1.into()), 42.into());
+    }
+
+    #[test]
+    fn rejects_values_beyond_64_bits() {
+        let genesis = br#"{ "timestamp": "0x10000000000000000", "alloc": {} }"#;
+        let genesis = Genesis::from_json(genesis).unwrap();
+        assert!(State::default().apply_genesis(&genesis).is_err());
+
+        let genesis = br#"{
+            "alloc": {
+                "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266": {
+                    "balance": "0x0",
+                    "nonce": "0x10000000000000000"
+                }
+            }
+        }"#;
+        let genesis = Genesis::from_json(genesis).unwrap();
+        assert!(State::default().apply_genesis(&genesis).is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use crate::tx::ExecutionError;
+use crate::types::TransactionResult;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::{keccak256, rlp::RlpStream};
+use foundry_evm::revm::BlockEnv;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, fmt, sync::Arc, sync::RwLock};
+
+const BLOCK_PREFIX: u8 = b'b';
+const BLOCK_HASH_PREFIX: u8 = b'h';
+const TRANSACTION_PREFIX: u8 = b't';
+const RECEIPT_PREFIX: u8 = b'r';
+
+/// Minimal key-value store interface for the chain history.
+pub trait KvStore: Send + Sync {
+    fn get(&self, key: &[u8]) -> eyre::Result<Option<Vec<u8>>>;
+    /// Atomically writes all the entries.
+    fn write(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> eyre::Result<()>;
+}
+
+/// History kept in memory, lost on restart.
+#[derive(Default)]
+pub struct MemoryKv(RwLock<HashMap<Vec<u8>, Vec<u8>>>);
+
+impl KvStore for MemoryKv {
+    fn get(&self, key: &[u8]) -> eyre::Result<Option<Vec<u8>>> {
+        let map = self.0.read().map_err(|_| eyre::eyre!("poisoned lock"))?;
+        Ok(map.get(key).cloned())
+    }
+
+    fn write(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> eyre::Result<()> {
+        let mut map = self.0.write().map_err(|_| eyre::eyre!("poisoned lock"))?;
+        map.extend(entries);
+        Ok(())
+    }
+}
+
+/// An Ethereum-like block, built by the app for every height delivered by the Engine.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Block {
+    pub number: U64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub timestamp: U64,
+    pub state_root: H256,
+    /// The recipient of the priority fees
+    pub miner: Address,
+    pub gas_limit: U256,
+    pub gas_used: U256,
+    pub base_fee_per_gas: U256,
+    pub logs_bloom: Bloom,
+    /// The hashes of the transactions executed in the block
+    pub transactions: Vec<H256>,
+}
+
+impl Block {
+    /// keccak256(RLP([parent_hash, miner, state_root, logs_bloom, number, gas_limit, gas_used,
+    /// timestamp, base_fee_per_gas, transactions]))
+    pub fn compute_hash(&self) -> H256 {
+        let mut stream = RlpStream::new_list(10);
+        stream.append(&self.parent_hash);
+        stream.append(&self.miner);
+        stream.append(&self.state_root);
+        stream.append(&self.logs_bloom.as_bytes().to_vec());
+        stream.append(&self.number.as_u64());
+        stream.append(&self.gas_limit);
+        stream.append(&self.gas_used);
+        stream.append(&self.timestamp.as_u64());
+        stream.append(&self.base_fee_per_gas);
+        stream.append_list(&self.transactions);
+        H256(keccak256(stream.out()))
+    }
+}
+
+/// A transaction included in a block.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockTransaction {
+    pub hash: H256,
+    pub from: Address,
+    pub block_hash: H256,
+    pub block_number: U64,
+    pub transaction_index: U64,
+    pub transaction: TypedTransaction,
+}
+
+/// The outcome of a transaction included in a block.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Receipt {
+    pub transaction_hash: H256,
+    pub transaction_index: U64,
+    pub block_hash: H256,
+    pub block_number: U64,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub cumulative_gas_used: U256,
+    pub gas_used: U256,
+    /// The address of the created contract, if the transaction was a successful deployment
+    pub contract_address: Option<Address>,
+    pub logs: Vec<Log>,
+    pub logs_bloom: Bloom,
+    /// 1 on success, 0 if the transaction reverted or halted
+    pub status: U64,
+}
+
+/// A block along with its transactions and their receipts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SealedBlock {
+    pub block: Block,
+    pub transactions: Vec<BlockTransaction>,
+    pub receipts: Vec<Receipt>,
+}
+
+/// The block being built while its transactions get delivered.
+#[derive(Debug, Clone, Default)]
+pub struct PendingBlock {
+    transactions: Vec<BlockTransaction>,
+    receipts: Vec<Receipt>,
+    gas_used: U256,
+    logs_bloom: Bloom,
+}
+
+impl PendingBlock {
+    /// Adds an executed transaction to the block. The block fields of the transaction and its
+    /// receipt get filled in when the block is sealed.
+    pub fn push(&mut self, hash: H256, from: Address, result: &TransactionResult) {
+        let index = U64::from(self.transactions.len());
+        let gas_used = U256::from(result.gas);
+        self.gas_used += gas_used;
+
+        let mut logs_bloom = Bloom::default();
+        let logs = result
+            .logs
+            .iter()
+            .enumerate()
+            .map(|(i, log)| {
+                logs_bloom.accrue(BloomInput::Raw(log.address.as_bytes()));
+                for topic in &log.topics {
+                    logs_bloom.accrue(BloomInput::Raw(topic.as_bytes()));
+                }
+                Log {
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: log.data.clone().into(),
+                    transaction_hash: Some(hash),
+                    transaction_index: Some(index),
+                    transaction_log_index: Some(i.into()),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        self.logs_bloom.accrue_bloom(&logs_bloom);
+
+        let success = ExecutionError::from_exit(result.exit).is_none();
+        let contract_address = result.contract_address();
+        let to = match result.transaction.to() {
+            Some(NameOrAddress::Address(to)) => Some(*to),
+            _ => None,
+        };
+
+        self.receipts.push(Receipt {
+            transaction_hash: hash,
+            transaction_index: index,
+            from,
+            to,
+            cumulative_gas_used: self.gas_used,
+            gas_used,
+            contract_address,
+            logs,
+            logs_bloom,
+            status: (success as u64).into(),
+            ..Default::default()
+        });
+        self.transactions.push(BlockTransaction {
+            hash,
+            from,
+            block_hash: H256::zero(),
+            block_number: U64::zero(),
+            transaction_index: index,
+            transaction: result.transaction.clone(),
+        });
+    }
+
+    /// The gas used by the transactions delivered so far.
+    pub fn gas_used(&self) -> U256 {
+        self.gas_used
+    }
+
+    /// Seals the block on top of its parent, with the parameters it was executed with.
+    pub fn seal(
+        self,
+        parent: &Block,
+        number: u64,
+        env: &BlockEnv,
+        state_root: H256,
+    ) -> SealedBlock {
+        let mut block = Block {
+            number: number.into(),
+            hash: H256::zero(),
+            parent_hash: parent.hash,
+            timestamp: env.timestamp.as_u64().into(),
+            state_root,
+            miner: env.coinbase,
+            gas_limit: env.gas_limit,
+            gas_used: self.gas_used,
+            base_fee_per_gas: env.basefee,
+            logs_bloom: self.logs_bloom,
+            transactions: self.transactions.iter().map(|tx| tx.hash).collect(),
+        };
+        block.hash = block.compute_hash();
+
+        let mut transactions = self.transactions;
+        for tx in &mut transactions {
+            tx.block_hash = block.hash;
+            tx.block_number = block.number;
+        }
+        let mut receipts = self.receipts;
+        let mut log_index = 0u64;
+        for receipt in &mut receipts {
+            receipt.block_hash = block.hash;
+            receipt.block_number = block.number;
+            for log in &mut receipt.logs {
+                log.block_hash = Some(block.hash);
+                log.block_number = Some(block.number);
+                log.log_index = Some(log_index.into());
+                log_index += 1;
+            }
+        }
+
+        SealedBlock {
+            block,
+            transactions,
+            receipts,
+        }
+    }
+}
+
+/// The committed blocks, indexed by number and hash.
+#[derive(Clone)]
+pub struct History {
+    kv: Arc<dyn KvStore>,
+}
+
+impl fmt::Debug for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("History").finish()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(Arc::new(MemoryKv::default()))
+    }
+}
+
+impl History {
+    pub fn new(kv: Arc<dyn KvStore>) -> Self {
+        Self { kv }
+    }
+
+    /// Atomically stores the block, its transactions and their receipts.
+    pub fn insert_block(&self, sealed: &SealedBlock) -> eyre::Result<()> {
+        let block = &sealed.block;
+        let number = block.number.as_u64();
+        let mut entries = vec![
+            (block_key(number), serde_json::to_vec(block)?),
+            (
+                hash_key(BLOCK_HASH_PREFIX, block.hash),
+                number.to_be_bytes().to_vec(),
+            ),
+        ];
+        for tx in &sealed.transactions {
+            entries.push((
+                hash_key(TRANSACTION_PREFIX, tx.hash),
+                serde_json::to_vec(tx)?,
+            ));
+        }
+        for receipt in &sealed.receipts {
+            entries.push((
+                hash_key(RECEIPT_PREFIX, receipt.transaction_hash),
+                serde_json::to_vec(receipt)?,
+            ));
+        }
+        self.kv.write(entries)
+    }
+
+    pub fn block_by_number(&self, number: u64) -> eyre::Result<Option<Block>> {
+        self.get(&block_key(number))
+    }
+
+    pub fn block_by_hash(&self, hash: H256) -> eyre::Result<Option<Block>> {
+        match self.kv.get(&hash_key(BLOCK_HASH_PREFIX, hash))? {
+            Some(number) => {
+                let number = u64::from_be_bytes(
+                    number
+                        .as_slice()
+                        .try_into()
+                        .map_err(|_| eyre::eyre!("corrupted block number"))?,
+                );
+                self.block_by_number(number)
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn transaction(&self, hash: H256) -> eyre::Result<Option<BlockTransaction>> {
+        self.get(&hash_key(TRANSACTION_PREFIX, hash))
+    }
+
+    pub fn receipt(&self, hash: H256) -> eyre::Result<Option<Receipt>> {
+        self.get(&hash_key(RECEIPT_PREFIX, hash))
+    }
+
+    fn get<T: DeserializeOwned>(&self, key: &[u8]) -> eyre::Result<Option<T>> {
+        self.kv
+            .get(key)?
+            .map(|value| serde_json::from_slice(&value))
+            .transpose()
+            .map_err(Into::into)
+    }
+}
+
+fn block_key(number: u64) -> Vec<u8> {
+    let mut key = vec![BLOCK_PREFIX];
+    key.extend_from_slice(&number.to_be_bytes());
+    key
+}
+
+fn hash_key(prefix: u8, hash: H256) -> Vec<u8> {
+    let mut key = vec![prefix];
+    key.extend_from_slice(hash.as_bytes());
+    key
+}
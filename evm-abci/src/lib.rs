@@ -2,4 +2,34 @@ mod app;
 pub use app::App;
 
 pub mod types;
-pub use types::{Consensus, Info, Mempool, Snapshot, State};
+pub use types::{CheckState, Consensus, Info, Mempool, QueryError, QueryFailure, Snapshot, State};
+
+pub mod rpc;
+pub use rpc::EthRpc;
+
+pub mod tx;
+pub use tx::{ExecutionError, SignedTransaction, TxError};
+
+pub mod trie;
+pub use trie::{Account, AccountProof, StateTrie, StorageProof};
+
+pub mod changes;
+pub use changes::Changes;
+
+pub mod db;
+pub use db::{MemoryDb, Persist, PersistentDb};
+
+pub mod gas;
+pub use gas::GasConfig;
+
+pub mod spec;
+pub use spec::{ChainSpec, Hardfork};
+
+pub mod genesis;
+pub use genesis::{Genesis, GenesisDb};
+
+pub mod history;
+pub use history::{Block, History, Receipt};
+
+pub mod versions;
+pub use versions::StateVersions;
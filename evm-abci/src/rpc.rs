@@ -0,0 +1,647 @@
+use crate::history::{Block, BlockTransaction};
+use crate::types::{Query, QueryFailure, QueryResponse};
+use crate::ExecutionError;
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use foundry_evm::revm::{Return, TransactOut};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use warp::{Filter, Rejection};
+
+/// Standard JSON-RPC 2.0 error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// Error code used by geth & co. for transactions which were not accepted in the mempool.
+const TRANSACTION_REJECTED: i64 = -32000;
+/// Error code used by geth & co. for requests which the node cannot serve, e.g. at a pruned
+/// height.
+const SERVER_ERROR: i64 = -32000;
+/// Error code used by geth & co. for reverted calls.
+const EXECUTION_REVERTED: i64 = 3;
+
+#[derive(Deserialize, Debug)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize, Debug)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize, Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+impl Response {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(res) => (Some(res), None),
+            Err(err) => (None, Some(err)),
+        };
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Ethereum JSON-RPC 2.0 server which sits in front of a primary's `AbciApi`:
+/// * `eth_sendRawTransaction` is forwarded to the `broadcast_tx` endpoint, which validates it
+/// with CheckTx.
+/// * All state reads are translated to [`Query`]s and sent to the `abci_query` endpoint.
+#[derive(Clone, Debug)]
+pub struct EthRpc {
+    /// The URL of the primary's ABCI API (e.g. `http://127.0.0.1:3002`)
+    api: String,
+    client: reqwest::Client,
+}
+
+impl EthRpc {
+    pub fn new(api: impl Into<String>) -> Self {
+        Self {
+            api: api.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Serves single and batched JSON-RPC requests POST'ed to `/`.
+    pub fn routes(self) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::post()
+            .and(warp::path::end())
+            .and(warp::body::bytes())
+            .and_then(move |body: warp::hyper::body::Bytes| {
+                let rpc = self.clone();
+                async move {
+                    let res = match serde_json::from_slice(&body) {
+                        Ok(body) => rpc.handle(body).await,
+                        Err(err) => response_value(Response::new(
+                            Value::Null,
+                            Err(RpcError::new(PARSE_ERROR, err)),
+                        )),
+                    };
+                    Ok::<_, Rejection>(warp::reply::json(&res))
+                }
+            })
+    }
+
+    async fn handle(&self, body: Value) -> Value {
+        match body {
+            Value::Array(reqs) if !reqs.is_empty() => {
+                let mut responses = Vec::with_capacity(reqs.len());
+                for req in reqs {
+                    responses.push(self.handle_one(req).await);
+                }
+                response_value(responses)
+            }
+            body => response_value(self.handle_one(body).await),
+        }
+    }
+
+    async fn handle_one(&self, req: Value) -> Response {
+        let req: Request = match serde_json::from_value(req) {
+            Ok(req) => req,
+            Err(err) => {
+                return Response::new(Value::Null, Err(RpcError::new(INVALID_REQUEST, err)))
+            }
+        };
+        if req.jsonrpc != "2.0" {
+            return Response::new(
+                req.id,
+                Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")),
+            );
+        }
+
+        tracing::debug!(method = %req.method, "rpc request");
+        let res = self.dispatch(&req.method, req.params).await;
+        Response::new(req.id, res)
+    }
+
+    async fn dispatch(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let res = match method {
+            "eth_sendRawTransaction" => {
+                let (raw,): (Bytes,) = parse_params(params)?;
+                to_value(self.send_raw_transaction(raw).await?)?
+            }
+            "eth_call" => {
+                let height = block_param(&params, 1)?;
+                let (tx,): (TransactionRequest,) = parse_params(params)?;
+                let res = self.query_at(Query::EthCall(tx), height).await?;
+                match res {
+                    QueryResponse::Tx(res) => match (res.exit, res.out) {
+                        (Return::Revert, TransactOut::Call(out)) => {
+                            return Err(RpcError {
+                                code: EXECUTION_REVERTED,
+                                message: "execution reverted".to_string(),
+                                data: Some(to_value(Bytes::from(out))?),
+                            })
+                        }
+                        (Return::Stop | Return::Return | Return::SelfDestruct, out) => {
+                            let out = match out {
+                                TransactOut::Call(out) => Bytes::from(out),
+                                TransactOut::Create(out, _) => Bytes::from(out),
+                                TransactOut::None => Bytes::default(),
+                            };
+                            to_value(out)?
+                        }
+                        (exit, _) => {
+                            return Err(RpcError::new(
+                                INTERNAL_ERROR,
+                                format!("execution failed: {:?}", exit),
+                            ))
+                        }
+                    },
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_estimateGas" => {
+                let height = block_param(&params, 1)?;
+                let (tx,): (TransactionRequest,) = parse_params(params)?;
+                match self.query_at(Query::EstimateGas(tx), height).await? {
+                    QueryResponse::Gas(gas) => to_value(gas)?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getBalance" => {
+                let height = block_param(&params, 1)?;
+                let (address,): (Address,) = parse_params(params)?;
+                match self.query_at(Query::Balance(address), height).await? {
+                    QueryResponse::Balance(balance) => to_value(balance)?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getTransactionCount" => {
+                let height = block_param(&params, 1)?;
+                let (address,): (Address,) = parse_params(params)?;
+                match self.query_at(Query::Nonce(address), height).await? {
+                    QueryResponse::Nonce(nonce) => to_value(nonce)?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getCode" => {
+                let height = block_param(&params, 1)?;
+                let (address,): (Address,) = parse_params(params)?;
+                match self.query_at(Query::Code(address), height).await? {
+                    QueryResponse::Code(code) => to_value(code)?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getStorageAt" => {
+                let height = block_param(&params, 2)?;
+                let (address, slot): (Address, U256) = parse_params(params)?;
+                let mut buf = [0u8; 32];
+                slot.to_big_endian(&mut buf);
+                let query = Query::Storage(address, H256::from(buf));
+                match self.query_at(query, height).await? {
+                    QueryResponse::Storage(value) => to_value(value)?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getProof" => {
+                let height = block_param(&params, 2)?;
+                let (address, slots): (Address, Vec<H256>) = parse_params(params)?;
+                match self.query_at(Query::Proof(address, slots), height).await? {
+                    QueryResponse::Proof(proof) => to_value(proof)?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_chainId" => match self.query(Query::ChainId).await? {
+                QueryResponse::ChainId(id) => to_value(id)?,
+                res => return Err(unexpected(res)),
+            },
+            // there is no competition for block space, so the base fee is enough
+            "eth_gasPrice" => match self.query(Query::BaseFee).await? {
+                QueryResponse::BaseFee(base_fee) => to_value(base_fee)?,
+                res => return Err(unexpected(res)),
+            },
+            "eth_blockNumber" => match self.query(Query::BlockNumber).await? {
+                QueryResponse::BlockNumber(num) => to_value(num)?,
+                res => return Err(unexpected(res)),
+            },
+            "eth_getBlockByNumber" => {
+                let full = full_param(&params)?;
+                let (number,): (BlockNumber,) = parse_params(params)?;
+                let number = self.block_number(number).await?;
+                match self.query(Query::BlockByNumber(number)).await? {
+                    QueryResponse::Block(block) => self.block_object(block, full).await?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getBlockByHash" => {
+                let full = full_param(&params)?;
+                let (hash,): (H256,) = parse_params(params)?;
+                match self.query(Query::BlockByHash(hash)).await? {
+                    QueryResponse::Block(block) => self.block_object(block, full).await?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getTransactionByHash" => {
+                let (hash,): (H256,) = parse_params(params)?;
+                match self.query(Query::Transaction(hash)).await? {
+                    QueryResponse::Transaction(tx) => {
+                        tx.map(transaction_object).transpose()?.unwrap_or_default()
+                    }
+                    res => return Err(unexpected(res)),
+                }
+            }
+            "eth_getTransactionReceipt" => {
+                let (hash,): (H256,) = parse_params(params)?;
+                match self.query(Query::Receipt(hash)).await? {
+                    QueryResponse::Receipt(receipt) => to_value(receipt)?,
+                    res => return Err(unexpected(res)),
+                }
+            }
+            _ => {
+                return Err(RpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("method {} not found", method),
+                ))
+            }
+        };
+        Ok(res)
+    }
+
+    /// Forwards the signed transaction to the primary, which runs it through the application's
+    /// CheckTx before it reaches the mempool. A transaction it rejects, e.g. because of a bad
+    /// signature or nonce, fails with a [`TRANSACTION_REJECTED`] error whose message is the
+    /// CheckTx log and whose data is the primary's response, with the CheckTx `code`.
+    async fn send_raw_transaction(&self, raw: Bytes) -> Result<H256, RpcError> {
+        let res = self
+            .client
+            .get(format!("{}/broadcast_tx", self.api))
+            .query(&[("tx", format!("0x{}", hex::encode(&raw)))])
+            .send()
+            .await
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err))?;
+        let res = res
+            .bytes()
+            .await
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err))?;
+        let res: Value =
+            serde_json::from_slice(&res).map_err(|err| RpcError::new(INTERNAL_ERROR, err))?;
+
+        // the tx was rejected by CheckTx
+        if res["code"].as_u64().unwrap_or_default() != 0 {
+            return Err(RpcError {
+                code: TRANSACTION_REJECTED,
+                message: res["log"].as_str().unwrap_or_default().to_string(),
+                data: Some(res),
+            });
+        }
+
+        Ok(H256(keccak256(&raw)))
+    }
+
+    /// Resolves a block tag to a block number. There are no pending blocks, so `pending` is the
+    /// latest block.
+    async fn block_number(&self, number: BlockNumber) -> Result<u64, RpcError> {
+        match number {
+            BlockNumber::Number(number) => Ok(number.as_u64()),
+            BlockNumber::Earliest => Ok(0),
+            _ => match self.query(Query::BlockNumber).await? {
+                QueryResponse::BlockNumber(number) => Ok(number.as_u64()),
+                res => Err(unexpected(res)),
+            },
+        }
+    }
+
+    /// The block, listing the hashes of its transactions, or the transactions themselves if
+    /// `full` is set.
+    async fn block_object(&self, block: Option<Block>, full: bool) -> Result<Value, RpcError> {
+        let block = match block {
+            Some(block) => block,
+            None => return Ok(Value::Null),
+        };
+        let mut object = to_value(&block)?;
+        if full {
+            let mut txs = Vec::new();
+            for hash in block.transactions {
+                match self.query(Query::Transaction(hash)).await? {
+                    QueryResponse::Transaction(Some(tx)) => txs.push(transaction_object(tx)?),
+                    QueryResponse::Transaction(None) => {
+                        return Err(RpcError::new(
+                            INTERNAL_ERROR,
+                            format!("transaction {:?} of block {} not found", hash, block.number),
+                        ))
+                    }
+                    res => return Err(unexpected(res)),
+                }
+            }
+            object["transactions"] = Value::Array(txs);
+        }
+        Ok(object)
+    }
+
+    /// Sends the query to the primary's `abci_query` endpoint, for the latest height.
+    async fn query(&self, query: Query) -> Result<QueryResponse, RpcError> {
+        self.query_at(query, 0).await
+    }
+
+    /// Sends the query to the primary's `abci_query` endpoint, for the state at `height`, 0
+    /// being the latest height.
+    async fn query_at(&self, query: Query, height: u64) -> Result<QueryResponse, RpcError> {
+        let query =
+            serde_json::to_string(&query).map_err(|err| RpcError::new(INTERNAL_ERROR, err))?;
+
+        let res = self
+            .client
+            .get(format!("{}/abci_query", self.api))
+            .query(&[
+                ("data", query),
+                ("path", "".to_string()),
+                ("height", height.to_string()),
+            ])
+            .send()
+            .await
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err))?;
+        let val = res
+            .bytes()
+            .await
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err))?;
+
+        if let Ok(res) = serde_json::from_slice(&val) {
+            return Ok(res);
+        }
+        match serde_json::from_slice::<QueryFailure>(&val) {
+            Ok(failure) => Err(query_failure(failure)),
+            // e.g. the primary could not reach the app
+            Err(_) => Err(RpcError::new(
+                INTERNAL_ERROR,
+                String::from_utf8_lossy(&val).into_owned(),
+            )),
+        }
+    }
+}
+
+/// Parses positional params, ignoring any trailing ones (e.g. the block tag, which is parsed by
+/// [`block_param`]).
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = match params {
+        Value::Array(params) => params,
+        Value::Null => vec![],
+        _ => return Err(RpcError::new(INVALID_PARAMS, "params must be an array")),
+    };
+    let len = std::cmp::max(params.len(), 1);
+    // try to decode with as many params as possible, so that optional trailing ones are ignored
+    (1..=len)
+        .rev()
+        .find_map(|n| {
+            serde_json::from_value(Value::Array(params.iter().take(n).cloned().collect())).ok()
+        })
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "invalid params"))
+}
+
+/// Parses the optional block tag at `index` of the params as a query height, 0 being the
+/// latest height. The states are indexed by the height which committed them, so the genesis
+/// state cannot be queried.
+fn block_param(params: &Value, index: usize) -> Result<u64, RpcError> {
+    let number = match params.get(index) {
+        None | Some(Value::Null) => return Ok(0),
+        Some(number) => serde_json::from_value(number.clone())
+            .map_err(|_| RpcError::new(INVALID_PARAMS, "invalid block number"))?,
+    };
+    match number {
+        BlockNumber::Latest | BlockNumber::Pending => Ok(0),
+        BlockNumber::Number(number) if !number.is_zero() => Ok(number.as_u64()),
+        _ => Err(RpcError::new(
+            INVALID_PARAMS,
+            "the genesis state cannot be queried",
+        )),
+    }
+}
+
+/// Parses the `full` flag of the `eth_getBlockBy*` methods, which is the second param.
+fn full_param(params: &Value) -> Result<bool, RpcError> {
+    match params.get(1) {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Bool(full)) => Ok(*full),
+        Some(_) => Err(RpcError::new(INVALID_PARAMS, "invalid full flag")),
+    }
+}
+
+/// Flattens the transaction and its inclusion info in a single object, like
+/// `eth_getTransactionByHash` does.
+fn transaction_object(tx: BlockTransaction) -> Result<Value, RpcError> {
+    let mut object = to_value(&tx.transaction)?;
+    if let Value::Object(fields) = &mut object {
+        fields.insert("hash".to_string(), to_value(tx.hash)?);
+        fields.insert("from".to_string(), to_value(tx.from)?);
+        fields.insert("blockHash".to_string(), to_value(tx.block_hash)?);
+        fields.insert("blockNumber".to_string(), to_value(tx.block_number)?);
+        fields.insert(
+            "transactionIndex".to_string(),
+            to_value(tx.transaction_index)?,
+        );
+    }
+    Ok(object)
+}
+
+/// A result which cannot be serialized is an internal error, not a `null` result.
+fn to_value<T: Serialize>(val: T) -> Result<Value, RpcError> {
+    serde_json::to_value(val).map_err(|err| RpcError::new(INTERNAL_ERROR, err))
+}
+
+/// The responses only hold JSON values and strings, so they always serialize, but a failure
+/// is still reported as an internal error instead of an empty body.
+fn response_value<T: Serialize>(res: T) -> Value {
+    serde_json::to_value(res).unwrap_or_else(|err| {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": Value::Null,
+            "error": { "code": INTERNAL_ERROR, "message": err.to_string() },
+        })
+    })
+}
+
+/// Reverts are reported like `eth_call` does, with the revert data, and the other failures
+/// with the app's error code as data.
+fn query_failure(failure: QueryFailure) -> RpcError {
+    if failure.code == ExecutionError::Reverted.code() {
+        return RpcError {
+            code: EXECUTION_REVERTED,
+            message: failure.message,
+            data: Some(Value::String(format!(
+                "0x{}",
+                hex::encode(failure.data.unwrap_or_default())
+            ))),
+        };
+    }
+    RpcError {
+        code: SERVER_ERROR,
+        message: failure.message,
+        data: Some(Value::from(failure.code)),
+    }
+}
+
+fn unexpected(res: QueryResponse) -> RpcError {
+    RpcError::new(
+        INTERNAL_ERROR,
+        format!("unexpected query response: {:?}", res),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_params_ignoring_block_tag() {
+        let params = serde_json::json!(["0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB", "latest"]);
+        let (address,): (Address,) = parse_params(params).unwrap();
+        assert_eq!(
+            address,
+            "0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB"
+                .parse::<Address>()
+                .unwrap()
+        );
+
+        let params = serde_json::json!(["0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB", "0x0"]);
+        let (_, slot): (Address, U256) = parse_params(params).unwrap();
+        assert_eq!(slot, U256::zero());
+
+        let params = serde_json::json!(["0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB", "latest"]);
+        assert_eq!(block_param(&params, 1).unwrap(), 0);
+        assert_eq!(block_param(&params, 2).unwrap(), 0);
+        let params = serde_json::json!(["0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB", "0x5"]);
+        assert_eq!(block_param(&params, 1).unwrap(), 5);
+        let params = serde_json::json!(["0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB", "earliest"]);
+        assert!(block_param(&params, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_methods() {
+        let rpc = EthRpc::new("http://127.0.0.1:0");
+        let res = rpc
+            .handle(
+                serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "eth_foo", "params": []}),
+            )
+            .await;
+        assert_eq!(res["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(res["id"], 1);
+    }
+
+    /// Serves the same ABCI query response to any query, like a primary would.
+    fn mock_api(res: abci::types::ResponseQuery) -> EthRpc {
+        let route = warp::path("abci_query").map(move || res.value.clone());
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        EthRpc::new(format!("http://{}", address))
+    }
+
+    #[tokio::test]
+    async fn returns_full_blocks() {
+        let block = Block {
+            number: 1.into(),
+            hash: H256::random(),
+            transactions: vec![H256::random()],
+            ..Default::default()
+        };
+        let tx = BlockTransaction {
+            hash: block.transactions[0],
+            from: Address::random(),
+            block_hash: block.hash,
+            block_number: block.number,
+            transaction_index: 0.into(),
+            transaction: TransactionRequest::new().nonce(3).into(),
+        };
+        let res = {
+            let (block, tx) = (block.clone(), tx.clone());
+            move |query: Query| match query {
+                Query::BlockByHash(_) => QueryResponse::Block(Some(block.clone())),
+                Query::Transaction(_) => QueryResponse::Transaction(Some(tx.clone())),
+                query => panic!("unexpected query {:?}", query),
+            }
+        };
+        let route = warp::path("abci_query")
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(move |params: std::collections::HashMap<String, String>| {
+                let query = serde_json::from_str(&params["data"]).unwrap();
+                serde_json::to_vec(&res(query)).unwrap()
+            });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let rpc = EthRpc::new(format!("http://{}", address));
+
+        let req = |full: bool| {
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "eth_getBlockByHash",
+                "params": [block.hash, full],
+            })
+        };
+        let res = rpc.handle(req(false)).await;
+        assert_eq!(
+            res["result"]["transactions"],
+            to_value(&block.transactions).unwrap()
+        );
+
+        let res = rpc.handle(req(true)).await;
+        assert_eq!(
+            res["result"]["transactions"],
+            Value::Array(vec![transaction_object(tx).unwrap()])
+        );
+        assert_eq!(res["result"]["hash"], to_value(block.hash).unwrap());
+    }
+
+    #[tokio::test]
+    async fn returns_revert_data_of_failed_estimations() {
+        let output = Bytes::from(vec![0x08, 0xc3, 0x79, 0xa0]);
+        let rpc = mock_api(
+            crate::QueryError::Execution {
+                error: ExecutionError::Reverted,
+                output: output.clone(),
+            }
+            .into(),
+        );
+        let req = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_estimateGas",
+            "params": [{"to": "0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB"}],
+        });
+        let res = rpc.handle(req.clone()).await;
+        assert_eq!(res["error"]["code"], EXECUTION_REVERTED);
+        assert_eq!(res["error"]["data"], to_value(output).unwrap());
+
+        let rpc = mock_api(
+            crate::QueryError::PrunedHeight {
+                height: 1,
+                earliest: 2,
+            }
+            .into(),
+        );
+        let res = rpc.handle(req).await;
+        assert_eq!(res["error"]["code"], SERVER_ERROR);
+        assert_eq!(res["error"]["data"], 17);
+    }
+}
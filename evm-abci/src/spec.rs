@@ -0,0 +1,152 @@
+use foundry_evm::revm::SpecId;
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+/// The chain id used when none is configured, same as Ethereum mainnet's.
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+
+/// The hardforks which can be scheduled, by name. They are the ones the bundled revm
+/// implements, which predates Shanghai.
+const HARDFORKS: &[(&str, SpecId)] = &[
+    ("frontier", SpecId::FRONTIER),
+    ("homestead", SpecId::HOMESTEAD),
+    ("tangerine", SpecId::TANGERINE),
+    ("spurious_dragon", SpecId::SPURIOUS_DRAGON),
+    ("byzantium", SpecId::BYZANTIUM),
+    ("constantinople", SpecId::CONSTANTINOPLE),
+    ("petersburg", SpecId::PETERSBURG),
+    ("istanbul", SpecId::ISTANBUL),
+    ("berlin", SpecId::BERLIN),
+    ("london", SpecId::LONDON),
+    ("merge", SpecId::MERGE),
+    ("latest", SpecId::LATEST),
+];
+
+/// The hardforks after [`HARDFORKS`], which need a newer revm.
+const UNSUPPORTED_HARDFORKS: &[&str] = &["shanghai", "cancun"];
+
+/// A hardfork activated at a block height, written `<name>@<height>` (e.g. `london@100`), or
+/// just `<name>` to activate it at genesis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hardfork {
+    pub spec: SpecId,
+    pub height: u64,
+}
+
+impl Hardfork {
+    pub fn name(&self) -> &'static str {
+        HARDFORKS
+            .iter()
+            .find(|(_, spec)| *spec == self.spec)
+            .map(|(name, _)| *name)
+            .unwrap_or("unknown")
+    }
+}
+
+impl FromStr for Hardfork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, height) = match s.split_once('@') {
+            Some((name, height)) => {
+                let height = height
+                    .parse()
+                    .map_err(|_| format!("invalid activation height in {}", s))?;
+                (name, height)
+            }
+            None => (s, 0),
+        };
+        let name = name.to_lowercase().replace('-', "_");
+        if UNSUPPORTED_HARDFORKS.contains(&name.as_str()) {
+            return Err(format!(
+                "hardfork {} is not implemented by the bundled revm, the latest one is merge",
+                name
+            ));
+        }
+        let spec = HARDFORKS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, spec)| *spec)
+            .ok_or_else(|| {
+                let known = HARDFORKS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+                format!("unsupported hardfork {}, expected one of {:?}", name, known)
+            })?;
+        Ok(Self { spec, height })
+    }
+}
+
+impl fmt::Display for Hardfork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name(), self.height)
+    }
+}
+
+/// The chain id and the hardfork schedule of the chain. They drive execution, so they must be
+/// the same on all the nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainSpec {
+    pub chain_id: u64,
+    /// The spec activated at each height, which stays active until the next one
+    hardforks: BTreeMap<u64, SpecId>,
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHAIN_ID, &[])
+    }
+}
+
+impl ChainSpec {
+    /// Schedules the provided hardforks. Without any hardfork at genesis, the chain starts with
+    /// revm's latest spec.
+    pub fn new(chain_id: u64, hardforks: &[Hardfork]) -> Self {
+        let mut schedule = BTreeMap::new();
+        schedule.insert(0, SpecId::LATEST);
+        schedule.extend(hardforks.iter().map(|fork| (fork.height, fork.spec)));
+        Self {
+            chain_id,
+            hardforks: schedule,
+        }
+    }
+
+    /// The spec active at the provided block height.
+    pub fn spec_at(&self, height: u64) -> SpecId {
+        self.hardforks
+            .range(..=height)
+            .next_back()
+            .map(|(_, spec)| *spec)
+            .unwrap_or(SpecId::LATEST)
+    }
+
+    pub fn hardforks(&self) -> Vec<Hardfork> {
+        self.hardforks
+            .iter()
+            .map(|(height, spec)| Hardfork {
+                spec: *spec,
+                height: *height,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_hardforks() {
+        let hardforks = ["berlin", "London@10", "merge@20"]
+            .iter()
+            .map(|fork| fork.parse().unwrap())
+            .collect::<Vec<Hardfork>>();
+        let spec = ChainSpec::new(1, &hardforks);
+
+        assert_eq!(spec.spec_at(0), SpecId::BERLIN);
+        assert_eq!(spec.spec_at(9), SpecId::BERLIN);
+        assert_eq!(spec.spec_at(10), SpecId::LONDON);
+        assert_eq!(spec.spec_at(100), SpecId::MERGE);
+
+        assert_eq!(ChainSpec::default().spec_at(0), SpecId::LATEST);
+        assert!("unknown".parse::<Hardfork>().is_err());
+        assert!("london@soon".parse::<Hardfork>().is_err());
+    }
+}
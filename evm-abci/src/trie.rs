@@ -0,0 +1,868 @@
+use crate::changes::Changes;
+use cita_trie::{PatriciaTrie, Trie, DB};
+use ethers::prelude::*;
+use ethers::utils::{
+    keccak256,
+    rlp::{self, Rlp, RlpStream},
+};
+use foundry_evm::revm::{AccountInfo, Database, KECCAK_EMPTY};
+use hasher::HasherKeccak;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+};
+
+const NODE_PREFIX: u8 = b'n';
+const COUNT_PREFIX: u8 = b'u';
+const DROPPED_PREFIX: u8 = b'd';
+
+/// The root of the empty trie: keccak256(rlp(""))
+pub const EMPTY_ROOT: H256 = H256([
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+]);
+
+/// The Merkle-Patricia trie of the accounts, whose root is computed the same way as Ethereum's
+/// `stateRoot`. It is kept across blocks and only the accounts written since the last update get
+/// updated, along with their storage tries. Cloning it is cheap: the clones share the nodes, and
+/// each of them keeps reading the trie at its own root, whose nodes are kept until all the
+/// tries open at it are dropped.
+#[derive(Clone)]
+pub struct StateTrie {
+    nodes: TrieNodes,
+    root: H256,
+    open: Arc<OpenRoot>,
+}
+
+impl Default for StateTrie {
+    /// The empty trie, with its nodes in memory.
+    fn default() -> Self {
+        Self::new(TrieNodes::default(), EMPTY_ROOT)
+    }
+}
+
+impl fmt::Debug for StateTrie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateTrie")
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+/// The nodes of the state and storage tries, by hash.
+///
+/// Each node is counted once for every position it takes in the latest tries. When an update
+/// drops a node out of them, it is still needed by the earlier roots, so it only gets deleted
+/// once no [`StateTrie`] is open at a root older than that update anymore, e.g. when the
+/// retained states fall out of their window.
+#[derive(Clone, Default)]
+pub struct TrieNodes {
+    /// The nodes which are not persisted yet, i.e. all of them if the state is in memory
+    nodes: Arc<RwLock<HashMap<Vec<u8>, Vec<u8>>>>,
+    references: Arc<Mutex<References>>,
+    db: Option<Arc<rocksdb::DB>>,
+}
+
+/// The bookkeeping of which nodes can be deleted. Updates are numbered by generation, and each
+/// root belongs to the generation of the update which produced it.
+#[derive(Default)]
+struct References {
+    /// The number of positions of each node in the latest tries. With a database, only the
+    /// counts which changed since the last write are here.
+    counts: HashMap<Vec<u8>, u64>,
+    /// The generation of the latest root
+    generation: u64,
+    /// The number of open tries at the roots of each generation
+    roots: BTreeMap<u64, usize>,
+    /// The nodes dropped out of the latest tries, by the generation of the update which dropped
+    /// them, and the last generation at which each of them got dropped
+    dropped: BTreeMap<u64, Vec<Vec<u8>>>,
+    dropped_at: HashMap<Vec<u8>, u64>,
+    /// The deleted nodes, and the dropped ones, which are not written to the database yet
+    deleted: HashSet<Vec<u8>>,
+    marks: HashMap<Vec<u8>, bool>,
+}
+
+impl TrieNodes {
+    /// The nodes persisted in `db`, next to the state. The nodes which got dropped before the
+    /// app last stopped are deleted, since the earlier roots are not retained across restarts.
+    pub fn persistent(db: Arc<rocksdb::DB>) -> Result<Self, rocksdb::Error> {
+        let mut batch = rocksdb::WriteBatch::default();
+        let start = [DROPPED_PREFIX];
+        let mode = rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward);
+        for (key, _) in db.iterator(mode) {
+            if key.first() != Some(&DROPPED_PREFIX) {
+                break;
+            }
+            let hash = &key[1..];
+            if db.get(count_key(hash))?.is_none() {
+                batch.delete(node_key(hash));
+            }
+            batch.delete(&key);
+        }
+        db.write(batch)?;
+
+        Ok(Self {
+            nodes: Default::default(),
+            references: Default::default(),
+            db: Some(db),
+        })
+    }
+
+    /// Writes the nodes which are not persisted yet, along with their counts, and deletes the
+    /// unused ones, in the same batch as the rest of the commit. Nothing gets written in memory.
+    pub(crate) fn write(&self, mut batch: rocksdb::WriteBatch) -> Result<(), rocksdb::Error> {
+        let db = match &self.db {
+            Some(db) => db,
+            None => return Ok(()),
+        };
+        let mut references = self.references.lock().expect("poisoned lock");
+        let mut nodes = self.nodes.write().expect("poisoned lock");
+        for (hash, node) in nodes.iter() {
+            batch.put(node_key(hash), node);
+        }
+        for (hash, count) in &references.counts {
+            match count {
+                0 => batch.delete(count_key(hash)),
+                count => batch.put(count_key(hash), count.to_be_bytes()),
+            }
+        }
+        for hash in &references.deleted {
+            batch.delete(node_key(hash));
+            batch.delete(count_key(hash));
+        }
+        for (hash, dropped) in &references.marks {
+            match dropped {
+                true => batch.put(dropped_key(hash), []),
+                false => batch.delete(dropped_key(hash)),
+            }
+        }
+        db.write(batch)?;
+
+        nodes.clear();
+        references.counts.clear();
+        references.deleted.clear();
+        references.marks.clear();
+        Ok(())
+    }
+
+    /// Drops one position of the node out of the latest tries.
+    fn dereference(&self, hash: &[u8]) -> Result<(), rocksdb::Error> {
+        let mut references = self.references.lock().expect("poisoned lock");
+        self.dereference_locked(&mut references, hash)
+    }
+
+    /// Drops the positions of all the nodes of the trie at `root`, which is not part of the
+    /// latest tries anymore, e.g. the storage trie of a deleted account.
+    fn dereference_trie(&self, root: H256) -> eyre::Result<()> {
+        let mut hashes = vec![root.as_bytes().to_vec()];
+        while let Some(hash) = hashes.pop() {
+            if hash == EMPTY_ROOT.as_bytes() {
+                continue;
+            }
+            let node = self
+                .get(&hash)?
+                .ok_or_else(|| eyre::eyre!("missing trie node 0x{}", hex::encode(&hash)))?;
+            self.dereference(&hash)?;
+            children(&Rlp::new(&node), &mut hashes)?;
+        }
+        Ok(())
+    }
+
+    /// Starts the generation of the root being produced by an update.
+    fn next_generation(&self) -> u64 {
+        let mut references = self.references.lock().expect("poisoned lock");
+        references.generation += 1;
+        references.generation
+    }
+
+    fn count(&self, references: &References, hash: &[u8]) -> Result<u64, rocksdb::Error> {
+        if let Some(count) = references.counts.get(hash) {
+            return Ok(*count);
+        }
+        let count = match &self.db {
+            Some(db) => db.get(count_key(hash))?,
+            None => None,
+        };
+        Ok(count
+            .and_then(|count| count.try_into().ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or_default())
+    }
+
+    fn reference_locked(
+        &self,
+        references: &mut References,
+        hash: &[u8],
+    ) -> Result<(), rocksdb::Error> {
+        // the empty trie's root is the same everywhere, and never deleted
+        if hash == EMPTY_ROOT.as_bytes() {
+            return Ok(());
+        }
+        let count = self.count(references, hash)? + 1;
+        references.counts.insert(hash.to_vec(), count);
+        references.deleted.remove(hash);
+        Ok(())
+    }
+
+    fn dereference_locked(
+        &self,
+        references: &mut References,
+        hash: &[u8],
+    ) -> Result<(), rocksdb::Error> {
+        if hash == EMPTY_ROOT.as_bytes() {
+            return Ok(());
+        }
+        let count = self.count(references, hash)?.saturating_sub(1);
+        references.counts.insert(hash.to_vec(), count);
+        if count == 0 {
+            // the update in progress produces the next generation's root
+            let generation = references.generation + 1;
+            references
+                .dropped
+                .entry(generation)
+                .or_default()
+                .push(hash.to_vec());
+            references.dropped_at.insert(hash.to_vec(), generation);
+            self.mark(references, hash, true);
+        }
+        Ok(())
+    }
+
+    /// Records whether the node is dropped in the database, so that it still gets deleted if
+    /// the app stops before it is released.
+    fn mark(&self, references: &mut References, hash: &[u8], dropped: bool) {
+        if self.db.is_some() {
+            references.marks.insert(hash.to_vec(), dropped);
+        }
+    }
+
+    /// Deletes the nodes dropped by the updates which no open root predates anymore, unless
+    /// they got used again since.
+    fn release(&self) {
+        let mut references = self.references.lock().expect("poisoned lock");
+        let oldest = match references.roots.keys().next() {
+            Some(generation) => *generation,
+            None => references.generation,
+        };
+        let retained = references.dropped.split_off(&(oldest + 1));
+        let released = std::mem::replace(&mut references.dropped, retained);
+
+        let mut nodes = self.nodes.write().expect("poisoned lock");
+        for (generation, hashes) in released {
+            for hash in hashes {
+                // the node got dropped again by a later update
+                if references.dropped_at.get(&hash) != Some(&generation) {
+                    continue;
+                }
+                references.dropped_at.remove(&hash);
+                match self.count(&references, &hash) {
+                    Ok(0) => {}
+                    // the node got used again since it was dropped
+                    Ok(_) => {
+                        self.mark(&mut references, &hash, false);
+                        continue;
+                    }
+                    // the node is kept as if it were still used, until the next restart
+                    Err(_) => continue,
+                }
+                nodes.remove(&hash);
+                self.mark(&mut references, &hash, false);
+                if self.db.is_some() {
+                    references.deleted.insert(hash);
+                } else {
+                    references.counts.remove(&hash);
+                }
+            }
+        }
+    }
+}
+
+impl DB for TrieNodes {
+    type Error = rocksdb::Error;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        if let Some(node) = self.nodes.read().expect("poisoned lock").get(key) {
+            return Ok(Some(node.clone()));
+        }
+        match &self.db {
+            Some(db) => db.get(node_key(key)),
+            None => Ok(None),
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> Result<bool, Self::Error> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn insert(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Self::Error> {
+        self.insert_batch(vec![key], vec![value])
+    }
+
+    /// The nodes written by a trie commit take a new position in the latest tries.
+    fn insert_batch(&self, keys: Vec<Vec<u8>>, values: Vec<Vec<u8>>) -> Result<(), Self::Error> {
+        let mut references = self.references.lock().expect("poisoned lock");
+        for key in &keys {
+            self.reference_locked(&mut references, key)?;
+        }
+        let mut nodes = self.nodes.write().expect("poisoned lock");
+        nodes.extend(keys.into_iter().zip(values));
+        Ok(())
+    }
+
+    /// The nodes replaced by a trie commit are not part of the latest trie anymore, but they
+    /// may still be part of a retained one, so they are only dereferenced.
+    fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+        self.dereference(key)
+    }
+
+    fn remove_batch(&self, keys: &[Vec<u8>]) -> Result<(), Self::Error> {
+        let mut references = self.references.lock().expect("poisoned lock");
+        for key in keys {
+            self.dereference_locked(&mut references, key)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Keeps the nodes of a root from being deleted while a [`StateTrie`] is open at it.
+struct OpenRoot {
+    nodes: TrieNodes,
+    generation: u64,
+}
+
+impl OpenRoot {
+    /// Opens a root of the latest generation.
+    fn latest(nodes: TrieNodes) -> Self {
+        let generation = {
+            let mut references = nodes.references.lock().expect("poisoned lock");
+            let generation = references.generation;
+            *references.roots.entry(generation).or_default() += 1;
+            generation
+        };
+        Self { nodes, generation }
+    }
+}
+
+impl Drop for OpenRoot {
+    fn drop(&mut self) {
+        {
+            let mut references = self.nodes.references.lock().expect("poisoned lock");
+            if let Some(count) = references.roots.get_mut(&self.generation) {
+                *count -= 1;
+                if *count == 0 {
+                    references.roots.remove(&self.generation);
+                }
+            }
+        }
+        self.nodes.release();
+    }
+}
+
+/// An account leaf of the state trie.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    pub balance: U256,
+    pub nonce: U64,
+    pub code_hash: H256,
+    /// The root of the account's storage trie
+    pub storage_hash: H256,
+}
+
+impl Default for Account {
+    /// The empty account, which is not part of the trie.
+    fn default() -> Self {
+        Self {
+            balance: U256::zero(),
+            nonce: U64::zero(),
+            code_hash: KECCAK_EMPTY,
+            storage_hash: EMPTY_ROOT,
+        }
+    }
+}
+
+/// The proof of an account and of some of its storage slots, as returned by `eth_getProof`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountProof {
+    pub address: Address,
+    pub balance: U256,
+    pub nonce: U64,
+    pub code_hash: H256,
+    pub storage_hash: H256,
+    /// The trie nodes from the state root to the account, which prove its absence if it does
+    /// not exist
+    pub account_proof: Vec<Bytes>,
+    pub storage_proof: Vec<StorageProof>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageProof {
+    pub key: H256,
+    pub value: U256,
+    /// The trie nodes from the storage root to the slot
+    pub proof: Vec<Bytes>,
+}
+
+type NodeTrie = PatriciaTrie<TrieNodes, HasherKeccak>;
+
+impl StateTrie {
+    /// The trie at `root`, whose nodes are in `nodes`. It must be their latest root, e.g. the
+    /// one committed before the app started, since the nodes of the earlier ones are only kept
+    /// for the tries which were already open at them.
+    pub fn new(nodes: TrieNodes, root: H256) -> Self {
+        let open = Arc::new(OpenRoot::latest(nodes.clone()));
+        Self { nodes, root, open }
+    }
+
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    pub fn nodes(&self) -> &TrieNodes {
+        &self.nodes
+    }
+
+    /// Writes the current values of the changed accounts and storage slots, read from `db`, and
+    /// returns the new root. The values which did not change are not written, so that the
+    /// nodes they are in do not get counted twice.
+    pub fn update<Db: Database>(&mut self, db: &mut Db, changes: &Changes) -> eyre::Result<H256> {
+        let mut trie = self.open(self.root)?;
+        for (address, account) in changes.iter() {
+            let key = keccak256(address);
+            let info = db.basic(*address);
+            let leaf = trie.get(&key)?;
+            let previous_storage_hash = match &leaf {
+                Some(leaf) => decode_account(leaf)?.storage_hash,
+                None => EMPTY_ROOT,
+            };
+            // empty accounts (EIP-161) are not part of the state, so that reads which populate
+            // the cache do not change the root
+            if is_empty(&info) {
+                if leaf.is_some() {
+                    trie.remove(&key)?;
+                    self.nodes.dereference_trie(previous_storage_hash)?;
+                }
+                continue;
+            }
+
+            let mut storage_hash = previous_storage_hash;
+            if account.storage_cleared {
+                self.nodes.dereference_trie(storage_hash)?;
+                storage_hash = EMPTY_ROOT;
+            }
+            if !account.slots.is_empty() {
+                let mut storage = self.open(storage_hash)?;
+                for slot in &account.slots {
+                    let key = keccak256(slot_key(*slot));
+                    let value = db.storage(*address, *slot);
+                    let current = storage.get(&key)?;
+                    if value.is_zero() {
+                        if current.is_some() {
+                            storage.remove(&key)?;
+                        }
+                        continue;
+                    }
+                    let value = rlp::encode(&value).to_vec();
+                    if current.as_ref() != Some(&value) {
+                        storage.insert(key.to_vec(), value)?;
+                    }
+                }
+                // the commit writes the root again, so the previous one loses its position
+                let root = H256::from_slice(&storage.root()?);
+                self.nodes.dereference(storage_hash.as_bytes())?;
+                storage_hash = root;
+            }
+
+            let value = encode_account(&info, storage_hash);
+            if leaf.as_ref() != Some(&value) {
+                trie.insert(key.to_vec(), value)?;
+            }
+        }
+        let root = H256::from_slice(&trie.root()?);
+        self.nodes.dereference(self.root.as_bytes())?;
+
+        // the nodes dropped by this update get deleted once the previous root is not open anymore
+        self.nodes.next_generation();
+        self.open = Arc::new(OpenRoot::latest(self.nodes.clone()));
+        self.root = root;
+        Ok(self.root)
+    }
+
+    /// The account as committed to in the trie, empty if it does not exist.
+    pub fn account(&self, address: Address) -> eyre::Result<Account> {
+        let trie = self.open(self.root)?;
+        match trie.get(&keccak256(address))? {
+            Some(leaf) => Ok(decode_account(&leaf)?),
+            None => Ok(Account::default()),
+        }
+    }
+
+    /// The value of the storage slot as committed to in the trie, zero if it is not set.
+    pub fn storage(&self, address: Address, slot: U256) -> eyre::Result<U256> {
+        let storage = self.open(self.account(address)?.storage_hash)?;
+        match storage.get(&keccak256(slot_key(slot)))? {
+            Some(value) => Ok(rlp::decode(&value)?),
+            None => Ok(U256::zero()),
+        }
+    }
+
+    /// The EIP-1186 proof of the account and of the provided storage slots, against the root.
+    pub fn proof(&self, address: Address, slots: &[H256]) -> eyre::Result<AccountProof> {
+        let account = self.account(address)?;
+        let account_proof = self.open(self.root)?.get_proof(&keccak256(address))?;
+
+        let storage = self.open(account.storage_hash)?;
+        let storage_proof = slots
+            .iter()
+            .map(|slot| -> eyre::Result<StorageProof> {
+                let key = keccak256(slot);
+                let value = match storage.get(&key)? {
+                    Some(value) => rlp::decode(&value)?,
+                    None => U256::zero(),
+                };
+                Ok(StorageProof {
+                    key: *slot,
+                    value,
+                    proof: to_bytes(storage.get_proof(&key)?),
+                })
+            })
+            .collect::<eyre::Result<_>>()?;
+
+        Ok(AccountProof {
+            address,
+            balance: account.balance,
+            nonce: account.nonce,
+            code_hash: account.code_hash,
+            storage_hash: account.storage_hash,
+            account_proof: to_bytes(account_proof),
+            storage_proof,
+        })
+    }
+
+    /// The state trie, or a storage trie, at `root`.
+    fn open(&self, root: H256) -> eyre::Result<NodeTrie> {
+        let nodes = Arc::new(self.nodes.clone());
+        let hasher = Arc::new(HasherKeccak::new());
+        if root == EMPTY_ROOT {
+            return Ok(PatriciaTrie::new(nodes, hasher));
+        }
+        Ok(PatriciaTrie::from(nodes, hasher, root.as_bytes())?)
+    }
+}
+
+/// Empty accounts (EIP-161) are not part of the state.
+fn is_empty(info: &AccountInfo) -> bool {
+    info.nonce == 0
+        && info.balance.is_zero()
+        && (info.code_hash == KECCAK_EMPTY || info.code_hash.is_zero())
+}
+
+/// Accounts without code have a zero code hash in revm, but the empty code's hash in the trie.
+fn code_hash(info: &AccountInfo) -> H256 {
+    if info.code_hash.is_zero() {
+        KECCAK_EMPTY
+    } else {
+        info.code_hash
+    }
+}
+
+/// RLP([nonce, balance, storage_root, code_hash])
+fn encode_account(info: &AccountInfo, storage_hash: H256) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(4);
+    stream.append(&info.nonce);
+    stream.append(&info.balance);
+    stream.append(&storage_hash);
+    stream.append(&code_hash(info));
+    stream.out().to_vec()
+}
+
+fn decode_account(leaf: &[u8]) -> Result<Account, rlp::DecoderError> {
+    let rlp = Rlp::new(leaf);
+    Ok(Account {
+        nonce: rlp.val_at::<u64>(0)?.into(),
+        balance: rlp.val_at(1)?,
+        storage_hash: rlp.val_at(2)?,
+        code_hash: rlp.val_at(3)?,
+    })
+}
+
+/// Collects the hashes of the nodes referenced by an encoded node, including the ones of its
+/// children which are inlined in it.
+fn children(node: &Rlp, hashes: &mut Vec<Vec<u8>>) -> Result<(), rlp::DecoderError> {
+    match node.item_count()? {
+        // a branch: 16 children and a value
+        17 => {
+            for index in 0..16 {
+                child(&node.at(index)?, hashes)?;
+            }
+        }
+        // an extension or a leaf, told apart by the flag of their hex-prefix encoded path
+        2 => {
+            let path = node.at(0)?.data()?;
+            if path.first().map_or(false, |flag| flag & 0x20 == 0) {
+                child(&node.at(1)?, hashes)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn child(item: &Rlp, hashes: &mut Vec<Vec<u8>>) -> Result<(), rlp::DecoderError> {
+    if item.is_list() {
+        return children(item, hashes);
+    }
+    let data = item.data()?;
+    if data.len() == 32 {
+        hashes.push(data.to_vec());
+    }
+    Ok(())
+}
+
+fn node_key(hash: &[u8]) -> Vec<u8> {
+    prefixed(NODE_PREFIX, hash)
+}
+
+fn count_key(hash: &[u8]) -> Vec<u8> {
+    prefixed(COUNT_PREFIX, hash)
+}
+
+fn dropped_key(hash: &[u8]) -> Vec<u8> {
+    prefixed(DROPPED_PREFIX, hash)
+}
+
+fn prefixed(prefix: u8, hash: &[u8]) -> Vec<u8> {
+    let mut key = vec![prefix];
+    key.extend_from_slice(hash);
+    key
+}
+
+fn slot_key(slot: U256) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    slot.to_big_endian(&mut key);
+    key
+}
+
+fn to_bytes(proof: Vec<Vec<u8>>) -> Vec<Bytes> {
+    proof.into_iter().map(Bytes::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cita_trie::MemoryDB;
+    use foundry_evm::revm::db::{CacheDB, EmptyDB};
+
+    /// A database and the changes of its writes.
+    struct TestDb {
+        db: CacheDB<EmptyDB>,
+        changes: Changes,
+    }
+
+    impl TestDb {
+        fn new() -> Self {
+            Self {
+                db: CacheDB::new(EmptyDB()),
+                changes: Changes::default(),
+            }
+        }
+
+        fn insert_account(&mut self, address: Address, balance: u64, nonce: u64) {
+            let info = AccountInfo {
+                balance: balance.into(),
+                nonce,
+                ..Default::default()
+            };
+            self.db.insert_account_info(address, info);
+            self.changes.account(address);
+        }
+
+        fn insert_storage(&mut self, address: Address, slot: u64, value: u64) {
+            self.db
+                .insert_account_storage(address, slot.into(), value.into());
+            self.changes.slot(address, slot.into());
+        }
+
+        /// Updates the trie with the changes since the last update.
+        fn update(&mut self, trie: &mut StateTrie) -> H256 {
+            let changes = std::mem::take(&mut self.changes);
+            trie.update(&mut self.db, &changes).unwrap()
+        }
+    }
+
+    #[test]
+    fn empty_state_root() {
+        let mut db = TestDb::new();
+        let mut trie = StateTrie::default();
+        let expected: H256 = "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+            .parse()
+            .unwrap();
+        assert_eq!(trie.root(), expected);
+        assert_eq!(db.update(&mut trie), expected);
+    }
+
+    #[test]
+    fn state_root_is_deterministic() {
+        let alice = Address::random();
+        let bob = Address::random();
+
+        let mut db1 = TestDb::new();
+        db1.insert_account(alice, 1, 0);
+        db1.insert_account(bob, 2, 0);
+        db1.insert_storage(bob, 1, 5);
+        let mut trie1 = StateTrie::default();
+        db1.update(&mut trie1);
+
+        let mut db2 = TestDb::new();
+        db2.insert_storage(bob, 1, 5);
+        db2.insert_account(bob, 2, 0);
+        db2.insert_account(alice, 1, 0);
+        // reading a non-existing account does not change the root
+        db2.changes.account(Address::random());
+        let mut trie2 = StateTrie::default();
+        db2.update(&mut trie2);
+
+        assert_eq!(trie1.root(), trie2.root());
+
+        db2.insert_account(alice, 3, 0);
+        db2.update(&mut trie2);
+        assert_ne!(trie1.root(), trie2.root());
+    }
+
+    #[test]
+    fn updates_match_a_rebuilt_trie() {
+        let (alice, bob, carol) = (Address::random(), Address::random(), Address::random());
+        let mut db = TestDb::new();
+        db.insert_account(alice, 1, 0);
+        db.insert_account(bob, 2, 1);
+        db.insert_storage(bob, 1, 5);
+        db.insert_storage(bob, 2, 6);
+        let mut trie = StateTrie::default();
+        db.update(&mut trie);
+        let previous = trie.clone();
+
+        // only the changed accounts and slots get updated
+        db.insert_account(carol, 3, 0);
+        db.insert_storage(bob, 2, 0);
+        db.insert_account(alice, 0, 0);
+        db.update(&mut trie);
+
+        let mut rebuilt = TestDb::new();
+        rebuilt.insert_account(bob, 2, 1);
+        rebuilt.insert_storage(bob, 1, 5);
+        rebuilt.insert_account(carol, 3, 0);
+        assert_eq!(trie.root(), rebuilt.update(&mut StateTrie::default()));
+        assert_eq!(trie.account(alice).unwrap(), Account::default());
+        assert_eq!(trie.storage(bob, 2.into()).unwrap(), U256::zero());
+
+        // the previous root can still be read
+        assert_eq!(previous.account(alice).unwrap().balance, 1.into());
+        assert_eq!(previous.storage(bob, 2.into()).unwrap(), 6.into());
+    }
+
+    #[test]
+    fn deletes_the_nodes_of_released_roots() {
+        let (alice, bob) = (Address::random(), Address::random());
+        let len = |trie: &StateTrie| trie.nodes.nodes.read().unwrap().len();
+        let mut db = TestDb::new();
+        db.insert_account(alice, 1, 0);
+        db.insert_account(bob, 2, 0);
+        db.insert_storage(bob, 1, 5);
+        let mut trie = StateTrie::default();
+        db.update(&mut trie);
+        let first = trie.clone();
+
+        db.insert_account(alice, 3, 0);
+        db.insert_storage(bob, 1, 6);
+        db.update(&mut trie);
+        let mut rebuilt = TestDb::new();
+        rebuilt.insert_account(alice, 3, 0);
+        rebuilt.insert_account(bob, 2, 0);
+        rebuilt.insert_storage(bob, 1, 6);
+        let mut expected = StateTrie::default();
+        assert_eq!(rebuilt.update(&mut expected), trie.root());
+        // the nodes of the first root are kept as long as it is open
+        assert!(len(&trie) > len(&expected));
+        assert_eq!(first.storage(bob, 1.into()).unwrap(), 5.into());
+        drop(first);
+        assert_eq!(len(&trie), len(&expected));
+
+        // the storage trie of a deleted account goes with it
+        db.insert_account(bob, 0, 0);
+        db.update(&mut trie);
+        let mut rebuilt = TestDb::new();
+        rebuilt.insert_account(alice, 3, 0);
+        let mut expected = StateTrie::default();
+        assert_eq!(rebuilt.update(&mut expected), trie.root());
+        assert_eq!(len(&trie), len(&expected));
+        assert_eq!(trie.account(alice).unwrap().balance, 3.into());
+    }
+
+    #[test]
+    fn proves_accounts_and_storage() {
+        let alice = Address::random();
+        let mut db = TestDb::new();
+        db.insert_account(alice, 1, 2);
+        db.insert_account(Address::random(), 3, 0);
+        db.insert_storage(alice, 1, 5);
+        let mut trie = StateTrie::default();
+        db.update(&mut trie);
+
+        let verify = |root: H256, key: &[u8], proof: &[Bytes]| {
+            let trie =
+                PatriciaTrie::new(Arc::new(MemoryDB::new(true)), Arc::new(HasherKeccak::new()));
+            let proof = proof.iter().map(|node| node.to_vec()).collect();
+            trie.verify_proof(root.as_bytes(), key, proof).unwrap()
+        };
+
+        let (slot, missing_slot) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        let proof = trie.proof(alice, &[slot, missing_slot]).unwrap();
+        assert_eq!(proof.balance, 1.into());
+        assert_eq!(proof.nonce, 2.into());
+        assert_eq!(proof.code_hash, KECCAK_EMPTY);
+        let info = AccountInfo {
+            balance: 1.into(),
+            nonce: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            verify(trie.root(), &keccak256(alice), &proof.account_proof),
+            Some(encode_account(&info, proof.storage_hash))
+        );
+        assert_eq!(proof.storage_proof[0].value, 5.into());
+        assert_eq!(
+            verify(
+                proof.storage_hash,
+                &keccak256(slot),
+                &proof.storage_proof[0].proof
+            ),
+            Some(rlp::encode(&U256::from(5)).to_vec())
+        );
+
+        // missing slots and accounts are proven absent
+        assert_eq!(proof.storage_proof[1].value, U256::zero());
+        assert_eq!(
+            verify(
+                proof.storage_hash,
+                &keccak256(missing_slot),
+                &proof.storage_proof[1].proof
+            ),
+            None
+        );
+        let bob = Address::random();
+        let proof = trie.proof(bob, &[]).unwrap();
+        assert_eq!(proof.balance, U256::zero());
+        assert_eq!(
+            verify(trie.root(), &keccak256(bob), &proof.account_proof),
+            None
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use ethers::prelude::*;
+use ethers::types::transaction::{eip2718::TypedTransaction, eip2930::Eip2930TransactionRequest};
+use ethers::utils::{keccak256, rlp};
+use foundry_evm::revm::Return;
+use std::fmt;
+
+/// Errors returned to the client when a transaction is rejected. Each variant maps to a
+/// distinct, non-zero ABCI response `code`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxError {
+    /// The payload is not a valid RLP-encoded signed transaction.
+    Decode(String),
+    /// The sender could not be recovered from the signature.
+    InvalidSignature(String),
+    /// The transaction is not replay protected or was signed for another chain.
+    WrongChainId { expected: U256, got: Option<U256> },
+    /// The sender's account has already used this nonce, e.g. the transaction is a replay.
+    NonceTooLow { expected: U256, got: U256 },
+    /// The transaction skips one of the sender's nonces.
+    NonceTooHigh { expected: U256, got: U256 },
+    /// The sender cannot pay for `value + gas * price`.
+    InsufficientFunds { balance: U256, cost: U256 },
+    /// The gas limit does not even cover the intrinsic cost of a transaction.
+    IntrinsicGasTooLow { minimum: U256, got: U256 },
+    /// The gas limit is above the block gas limit.
+    GasLimitTooHigh { maximum: U256, got: U256 },
+    /// The gas limit is above the gas left in the block being built.
+    BlockGasExhausted { remaining: U256, got: U256 },
+    /// The max fee per gas does not cover the block's base fee.
+    FeeCapTooLow { base_fee: U256, got: U256 },
+    /// The recipient is an ENS name, which cannot be resolved by the app.
+    UnresolvedName(String),
+}
+
+impl TxError {
+    /// The ABCI response code for this error. `0` is reserved for success.
+    pub fn code(&self) -> u32 {
+        match self {
+            TxError::Decode(_) => 1,
+            TxError::InvalidSignature(_) => 2,
+            TxError::WrongChainId { .. } => 3,
+            TxError::NonceTooLow { .. } => 4,
+            TxError::NonceTooHigh { .. } => 5,
+            TxError::InsufficientFunds { .. } => 6,
+            TxError::IntrinsicGasTooLow { .. } => 7,
+            TxError::GasLimitTooHigh { .. } => 8,
+            TxError::BlockGasExhausted { .. } => 9,
+            TxError::FeeCapTooLow { .. } => 10,
+            // 11 to 13 are the execution errors
+            TxError::UnresolvedName(_) => 14,
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::Decode(err) => write!(f, "could not decode transaction: {}", err),
+            TxError::InvalidSignature(err) => write!(f, "invalid signature: {}", err),
+            TxError::WrongChainId { expected, got } => {
+                write!(f, "wrong chain id: expected {}, got {:?}", expected, got)
+            }
+            TxError::NonceTooLow { expected, got } => {
+                write!(f, "nonce too low: expected {}, got {}", expected, got)
+            }
+            TxError::NonceTooHigh { expected, got } => {
+                write!(f, "nonce too high: expected {}, got {}", expected, got)
+            }
+            TxError::InsufficientFunds { balance, cost } => {
+                write!(f, "insufficient funds: balance {}, cost {}", balance, cost)
+            }
+            TxError::IntrinsicGasTooLow { minimum, got } => {
+                write!(f, "intrinsic gas too low: minimum {}, got {}", minimum, got)
+            }
+            TxError::GasLimitTooHigh { maximum, got } => {
+                write!(f, "gas limit too high: maximum {}, got {}", maximum, got)
+            }
+            TxError::BlockGasExhausted { remaining, got } => {
+                write!(
+                    f,
+                    "block gas exhausted: remaining {}, got {}",
+                    remaining, got
+                )
+            }
+            TxError::FeeCapTooLow { base_fee, got } => {
+                write!(
+                    f,
+                    "max fee per gas below base fee: base fee {}, got {}",
+                    base_fee, got
+                )
+            }
+            TxError::UnresolvedName(name) => write!(f, "unresolved recipient name: {}", name),
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+/// Errors of the transactions which got executed but failed: they are still included in the
+/// block and charged for their gas. Their codes follow the [`TxError`] ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionError {
+    /// The transaction reverted, e.g. on a failed `require`.
+    Reverted,
+    /// The transaction ran out of gas.
+    OutOfGas,
+    /// The transaction halted with any other exceptional status.
+    Halted(Return),
+}
+
+impl ExecutionError {
+    /// `None` if the exit status is a success.
+    pub fn from_exit(exit: Return) -> Option<Self> {
+        match exit {
+            Return::Stop | Return::Return | Return::SelfDestruct => None,
+            Return::Revert => Some(ExecutionError::Reverted),
+            Return::OutOfGas => Some(ExecutionError::OutOfGas),
+            exit => Some(ExecutionError::Halted(exit)),
+        }
+    }
+
+    /// The ABCI response code for this error.
+    pub fn code(&self) -> u32 {
+        match self {
+            ExecutionError::Reverted => 11,
+            ExecutionError::OutOfGas => 12,
+            ExecutionError::Halted(_) => 13,
+        }
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::Reverted => write!(f, "execution reverted"),
+            ExecutionError::OutOfGas => write!(f, "out of gas"),
+            ExecutionError::Halted(exit) => write!(f, "execution halted: {:?}", exit),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// The gas charged for any transaction, before executing any code.
+pub const INTRINSIC_GAS: u64 = 21_000;
+
+/// The ABCI codespace of the errors returned by the app.
+pub const CODESPACE: &str = "evm";
+
+/// A transaction whose signature has been verified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedTransaction {
+    /// The hash of the raw transaction
+    pub hash: H256,
+    /// The sender, as recovered from the signature
+    pub from: Address,
+    /// The transaction, with `from` set to the recovered sender
+    pub tx: TypedTransaction,
+}
+
+impl SignedTransaction {
+    /// Decodes an RLP-encoded signed legacy, EIP-2930 or EIP-1559 transaction, recovers its
+    /// sender and checks that it was signed for `chain_id`.
+    pub fn decode(raw: &[u8], chain_id: U256) -> Result<Self, TxError> {
+        let tx: Transaction = rlp::decode(raw).map_err(|err| TxError::Decode(err.to_string()))?;
+
+        let from = tx
+            .recover_from()
+            .map_err(|err| TxError::InvalidSignature(err.to_string()))?;
+
+        let tx_chain_id = match tx.transaction_type.map(|ty| ty.as_u64()) {
+            // EIP-155: v = chain_id * 2 + 35 + {0, 1}, pre-EIP-155 signatures are unprotected
+            None | Some(0) => {
+                let v = tx.v.as_u64();
+                (v >= 35).then(|| U256::from((v - 35) / 2))
+            }
+            _ => tx.chain_id,
+        };
+        if tx_chain_id != Some(chain_id) {
+            return Err(TxError::WrongChainId {
+                expected: chain_id,
+                got: tx_chain_id,
+            });
+        }
+
+        Ok(Self {
+            hash: H256(keccak256(raw)),
+            from,
+            tx: typed(&tx, from, chain_id),
+        })
+    }
+}
+
+impl SignedTransaction {
+    /// The maximum amount the sender can be charged: `value + gas * price`, with the price
+    /// being the max fee of EIP-1559 transactions. `None` on overflow.
+    pub fn max_cost(&self) -> Option<U256> {
+        let gas = self.tx.gas().copied().unwrap_or_default();
+        let price = self.tx.gas_price().unwrap_or_default();
+        let value = self.tx.value().copied().unwrap_or_default();
+        gas.checked_mul(price)?.checked_add(value)
+    }
+}
+
+/// Converts a decoded transaction to the request type used for execution.
+fn typed(tx: &Transaction, from: Address, chain_id: U256) -> TypedTransaction {
+    let access_list = tx.access_list.clone().unwrap_or_default();
+    match tx.transaction_type.map(|ty| ty.as_u64()) {
+        Some(2) => {
+            let mut req = Eip1559TransactionRequest::new()
+                .from(from)
+                .value(tx.value)
+                .gas(tx.gas)
+                .nonce(tx.nonce)
+                .data(tx.input.clone())
+                .access_list(access_list)
+                .chain_id(chain_id.as_u64());
+            req.max_fee_per_gas = tx.max_fee_per_gas;
+            req.max_priority_fee_per_gas = tx.max_priority_fee_per_gas;
+            if let Some(to) = tx.to {
+                req = req.to(to);
+            }
+            req.into()
+        }
+        ty => {
+            let mut req = TransactionRequest::new()
+                .from(from)
+                .value(tx.value)
+                .gas(tx.gas)
+                .nonce(tx.nonce)
+                .data(tx.input.clone())
+                .chain_id(chain_id.as_u64());
+            req.gas_price = tx.gas_price;
+            if let Some(to) = tx.to {
+                req = req.to(to);
+            }
+            if ty == Some(1) {
+                Eip2930TransactionRequest::new(req, access_list).into()
+            } else {
+                req.into()
+            }
+        }
+    }
+}
+
+/// Signs the transaction with the provided wallet and returns its RLP encoding.
+#[cfg(test)]
+pub(crate) fn sign(wallet: &LocalWallet, tx: impl Into<TypedTransaction>) -> Vec<u8> {
+    let tx = tx.into();
+    let sig = wallet.sign_transaction_sync(&tx);
+    tx.rlp_signed(&sig).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::transaction::eip2930::{AccessList, AccessListItem};
+
+    const CHAIN_ID: u64 = 1337;
+
+    fn access_list() -> AccessList {
+        AccessList(vec![AccessListItem {
+            address: Address::random(),
+            storage_keys: vec![H256::random(), H256::random()],
+        }])
+    }
+
+    fn transfer(chain_id: u64) -> TransactionRequest {
+        TransactionRequest::new()
+            .to(Address::random())
+            .value(1000)
+            .gas(30_000)
+            .gas_price(7)
+            .nonce(3)
+            .data(vec![1, 2, 3])
+            .chain_id(chain_id)
+    }
+
+    #[test]
+    fn decodes_eip2930_transactions() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let access_list = access_list();
+        let tx = Eip2930TransactionRequest::new(transfer(CHAIN_ID), access_list.clone());
+        let raw = sign(&wallet, tx.clone());
+
+        let decoded = SignedTransaction::decode(&raw, CHAIN_ID.into()).unwrap();
+        assert_eq!(decoded.hash, H256(keccak256(&raw)));
+        assert_eq!(decoded.from, wallet.address());
+        assert_eq!(decoded.tx.chain_id(), Some(CHAIN_ID.into()));
+        match decoded.tx {
+            TypedTransaction::Eip2930(req) => {
+                assert_eq!(req.access_list, access_list);
+                assert_eq!(req.tx.from, Some(wallet.address()));
+                assert_eq!(req.tx.to, tx.tx.to);
+                assert_eq!(req.tx.value, tx.tx.value);
+                assert_eq!(req.tx.gas, tx.tx.gas);
+                assert_eq!(req.tx.gas_price, tx.tx.gas_price);
+                assert_eq!(req.tx.nonce, tx.tx.nonce);
+                assert_eq!(req.tx.data, tx.tx.data);
+            }
+            tx => panic!("expected an EIP-2930 transaction, got {:?}", tx),
+        }
+    }
+
+    #[test]
+    fn decodes_eip1559_transactions() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let access_list = access_list();
+        let tx = Eip1559TransactionRequest::new()
+            .to(Address::random())
+            .value(1000)
+            .gas(30_000)
+            .max_fee_per_gas(10)
+            .max_priority_fee_per_gas(2)
+            .nonce(3)
+            .data(vec![1, 2, 3])
+            .access_list(access_list.clone())
+            .chain_id(CHAIN_ID);
+        let raw = sign(&wallet, tx.clone());
+
+        let decoded = SignedTransaction::decode(&raw, CHAIN_ID.into()).unwrap();
+        assert_eq!(decoded.hash, H256(keccak256(&raw)));
+        assert_eq!(decoded.from, wallet.address());
+        assert_eq!(decoded.tx.chain_id(), Some(CHAIN_ID.into()));
+        // the max fee is what the sender can be charged at most
+        assert_eq!(decoded.max_cost(), Some(U256::from(30_000 * 10 + 1000)));
+        match decoded.tx {
+            TypedTransaction::Eip1559(req) => {
+                assert_eq!(req.access_list, access_list);
+                assert_eq!(req.from, Some(wallet.address()));
+                assert_eq!(req.to, tx.to);
+                assert_eq!(req.value, tx.value);
+                assert_eq!(req.gas, tx.gas);
+                assert_eq!(req.max_fee_per_gas, tx.max_fee_per_gas);
+                assert_eq!(req.max_priority_fee_per_gas, tx.max_priority_fee_per_gas);
+                assert_eq!(req.nonce, tx.nonce);
+                assert_eq!(req.data, tx.data);
+            }
+            tx => panic!("expected an EIP-1559 transaction, got {:?}", tx),
+        }
+    }
+
+    #[test]
+    fn decodes_legacy_transactions() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let raw = sign(&wallet, transfer(CHAIN_ID));
+
+        let decoded = SignedTransaction::decode(&raw, CHAIN_ID.into()).unwrap();
+        assert_eq!(decoded.from, wallet.address());
+        assert_eq!(decoded.tx.chain_id(), Some(CHAIN_ID.into()));
+        assert!(matches!(decoded.tx, TypedTransaction::Legacy(_)));
+    }
+
+    #[test]
+    fn rejects_transactions_of_other_chains() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let expected = TxError::WrongChainId {
+            expected: CHAIN_ID.into(),
+            got: Some(1.into()),
+        };
+
+        // the chain id of legacy transactions is recovered from the EIP-155 `v`
+        let raw = sign(&wallet, transfer(1));
+        assert_eq!(
+            SignedTransaction::decode(&raw, CHAIN_ID.into()),
+            Err(expected.clone())
+        );
+
+        // the typed ones carry it in their envelope
+        let raw = sign(
+            &wallet,
+            Eip2930TransactionRequest::new(transfer(1), access_list()),
+        );
+        assert_eq!(
+            SignedTransaction::decode(&raw, CHAIN_ID.into()),
+            Err(expected.clone())
+        );
+        let tx = Eip1559TransactionRequest::new()
+            .to(Address::random())
+            .gas(30_000)
+            .max_fee_per_gas(10)
+            .chain_id(1);
+        assert_eq!(
+            SignedTransaction::decode(&sign(&wallet, tx), CHAIN_ID.into()),
+            Err(expected)
+        );
+    }
+}
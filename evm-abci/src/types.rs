@@ -1,4 +1,15 @@
+use crate::changes::Changes;
+use crate::db::{MemoryDb, Persist};
+use crate::gas::GasConfig;
+use crate::genesis::{Genesis, GenesisDb};
+use crate::history::{Block, BlockTransaction, History, PendingBlock, Receipt, SealedBlock};
+use crate::spec::ChainSpec;
+use crate::trie::{Account, AccountProof, StateTrie};
+use crate::tx::{ExecutionError, SignedTransaction, TxError, CODESPACE, INTRINSIC_GAS};
+use crate::versions::StateVersions;
 use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::cmp::Ordering;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -13,9 +24,9 @@ use abci::{
 
 use foundry_evm::revm::{
     self,
-    db::{CacheDB, EmptyDB},
-    CreateScheme, Database, DatabaseCommit, Env, Log as RevmLog, Return, TransactOut, TransactTo,
-    TxEnv,
+    db::{CacheDB, DatabaseRef},
+    CreateScheme, Database, DatabaseCommit, Env, Log as RevmLog, Return, SpecId, TransactOut,
+    TransactTo, TxEnv,
 };
 
 /// The app's state, containing a Revm DB.
@@ -26,56 +37,258 @@ pub struct State<Db> {
     pub app_hash: Vec<u8>,
     pub db: Db,
     pub env: Env,
+    /// The transactions delivered since BeginBlock
+    pub pending_block: PendingBlock,
+    /// The block sealed at the last EndBlock
+    pub latest_block: SealedBlock,
+    /// The committed blocks
+    pub history: History,
+    pub gas_config: GasConfig,
+    pub chain_spec: ChainSpec,
+    /// The accounts and storage slots written since the last commit
+    pub changes: Changes,
+    /// The state trie, whose root is the app hash
+    pub trie: StateTrie,
 }
 
-impl Default for State<CacheDB<EmptyDB>> {
+impl Default for State<CacheDB<MemoryDb>> {
     fn default() -> Self {
-        Self {
+        let mut state = Self {
             block_height: 0,
             app_hash: Vec::new(),
-            db: CacheDB::new(EmptyDB()),
+            db: CacheDB::new(MemoryDb::default()),
             env: Default::default(),
+            pending_block: Default::default(),
+            latest_block: Default::default(),
+            history: Default::default(),
+            gas_config: Default::default(),
+            chain_spec: Default::default(),
+            changes: Default::default(),
+            trie: Default::default(),
+        };
+        state.set_gas_config(GasConfig::default());
+        state.set_chain_spec(ChainSpec::default());
+        state
+    }
+}
+
+impl<Db> State<Db> {
+    /// Sets the gas parameters of the chain, and the base fee of the next block accordingly.
+    pub fn set_gas_config(&mut self, config: GasConfig) {
+        self.env.block.gas_limit = config.block_gas_limit.into();
+        if let Some(coinbase) = config.coinbase {
+            self.env.block.coinbase = coinbase;
+        }
+        self.env.block.basefee = config.next_base_fee(&self.latest_block.block);
+        self.gas_config = config;
+    }
+
+    /// Sets the chain id and the hardfork schedule, activating the spec of the next block.
+    pub fn set_chain_spec(&mut self, spec: ChainSpec) {
+        self.env.cfg.chain_id = spec.chain_id.into();
+        self.env.cfg.spec_id = spec.spec_at(self.block_height as u64 + 1);
+        self.chain_spec = spec;
+    }
+}
+
+impl<Db: Database> State<Db> {
+    /// Updates the state trie with the changes since the last commit, and returns its root.
+    pub fn state_root(&mut self) -> H256 {
+        // the app hash cannot be computed without the trie, so there is no point in going on
+        self.trie
+            .update(&mut self.db, &self.changes)
+            .expect("could not update the state trie")
+    }
+}
+
+impl<Db: Persist> State<Db> {
+    /// A copy of the state for queries at its height, which shares the committed trie nodes
+    /// with it instead of copying its cache.
+    pub fn version(&self) -> Self {
+        State {
+            block_height: self.block_height,
+            app_hash: self.app_hash.clone(),
+            db: self.db.version(&self.trie),
+            env: self.env.clone(),
+            pending_block: self.pending_block.clone(),
+            latest_block: self.latest_block.clone(),
+            history: self.history.clone(),
+            gas_config: self.gas_config.clone(),
+            chain_spec: self.chain_spec.clone(),
+            changes: Changes::default(),
+            trie: self.trie.clone(),
         }
     }
 }
 
+impl<ExtDB: DatabaseRef> State<CacheDB<ExtDB>> {
+    /// Writes the account outside of a transaction, e.g. to fund it.
+    pub fn insert_account_info(&mut self, address: Address, info: revm::AccountInfo) {
+        self.db.insert_account_info(address, info);
+        self.changes.account(address);
+    }
+
+    pub fn insert_account_storage(&mut self, address: Address, slot: U256, value: U256) {
+        self.db.insert_account_storage(address, slot, value);
+        self.changes.slot(address, slot);
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct TransactionResult {
-    transaction: TransactionRequest,
-    exit: Return,
-    out: TransactOut,
-    gas: u64,
-    logs: Vec<RevmLog>,
+    pub transaction: TypedTransaction,
+    pub exit: Return,
+    pub out: TransactOut,
+    pub gas: u64,
+    pub logs: Vec<RevmLog>,
+}
+
+impl TransactionResult {
+    /// The address of the contract deployed by a successful creation.
+    pub fn contract_address(&self) -> Option<Address> {
+        match self.out {
+            TransactOut::Create(_, address) if ExecutionError::from_exit(self.exit).is_none() => {
+                address
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<Db: Database + DatabaseCommit> State<Db> {
+    /// Checks that the transaction can be executed on top of the current state: its nonce is
+    /// the sender's next one, its gas limit covers the intrinsic gas and fits in the block, its
+    /// max fee covers the base fee and the sender can pay for it.
+    fn validate(&mut self, signed: &SignedTransaction) -> Result<(), TxError> {
+        self.check_nonce(signed)?;
+
+        let minimum = U256::from(INTRINSIC_GAS);
+        let got = signed.tx.gas().copied().unwrap_or_default();
+        if got < minimum {
+            return Err(TxError::IntrinsicGasTooLow { minimum, got });
+        }
+        let maximum = self.env.block.gas_limit;
+        if got > maximum {
+            return Err(TxError::GasLimitTooHigh { maximum, got });
+        }
+        let remaining = maximum.saturating_sub(self.pending_block.gas_used());
+        if got > remaining {
+            return Err(TxError::BlockGasExhausted { remaining, got });
+        }
+
+        // there is no base fee before London
+        let base_fee = self.env.block.basefee;
+        let fee_cap = signed.tx.gas_price().unwrap_or_default();
+        let london = self.env.cfg.spec_id as u8 >= SpecId::LONDON as u8;
+        if london && fee_cap < base_fee {
+            return Err(TxError::FeeCapTooLow {
+                base_fee,
+                got: fee_cap,
+            });
+        }
+
+        let balance = self.db.basic(signed.from).balance;
+        let cost = signed.max_cost().unwrap_or(U256::MAX);
+        if balance < cost {
+            return Err(TxError::InsufficientFunds { balance, cost });
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the transaction uses the sender's next nonce, so that it can only ever be
+    /// executed once.
+    fn check_nonce(&mut self, signed: &SignedTransaction) -> Result<(), TxError> {
+        let expected = U256::from(self.db.basic(signed.from).nonce);
+        let got = signed.tx.nonce().copied().unwrap_or_default();
+        match got.cmp(&expected) {
+            Ordering::Less => Err(TxError::NonceTooLow { expected, got }),
+            Ordering::Greater => Err(TxError::NonceTooHigh { expected, got }),
+            Ordering::Equal => Ok(()),
+        }
+    }
+
+    /// Executes the transaction, and commits its changes unless `read_only`. Fails without
+    /// executing it if it cannot be turned into a revm transaction, e.g. its recipient is an
+    /// ENS name.
     async fn execute(
         &mut self,
-        tx: TransactionRequest,
+        tx: TypedTransaction,
         read_only: bool,
-    ) -> eyre::Result<TransactionResult> {
+    ) -> Result<TransactionResult, TxError> {
+        let transact_to = match tx.to() {
+            Some(NameOrAddress::Address(inner)) => TransactTo::Call(*inner),
+            Some(NameOrAddress::Name(name)) => return Err(TxError::UnresolvedName(name.clone())),
+            None => TransactTo::Create(CreateScheme::Create),
+        };
+        // revm only takes 64 bits gas limits and nonces
+        let gas_limit = tx.gas().copied().unwrap_or_default();
+        if gas_limit > U256::from(u64::MAX) {
+            return Err(TxError::GasLimitTooHigh {
+                maximum: u64::MAX.into(),
+                got: gas_limit,
+            });
+        }
+        let nonce = tx.nonce().copied().unwrap_or_default();
+        if nonce > U256::from(u64::MAX) {
+            let caller = tx.from().copied().unwrap_or_default();
+            return Err(TxError::NonceTooHigh {
+                expected: self.db.basic(caller).nonce.into(),
+                got: nonce,
+            });
+        }
+
+        // EIP-1559 transactions pay at most `max_fee_per_gas`, of which up to
+        // `max_priority_fee_per_gas` goes to the miner
+        let (gas_price, gas_priority_fee) = match &tx {
+            TypedTransaction::Eip1559(inner) => (
+                inner.max_fee_per_gas.unwrap_or_default(),
+                inner.max_priority_fee_per_gas,
+            ),
+            _ => {
+                let gas_price = tx.gas_price().unwrap_or_default();
+                (gas_price, Some(gas_price))
+            }
+        };
+        let access_list = match &tx {
+            TypedTransaction::Eip2930(inner) => inner.access_list.0.clone(),
+            TypedTransaction::Eip1559(inner) => inner.access_list.0.clone(),
+            TypedTransaction::Legacy(_) => vec![],
+        };
+
         let mut evm = revm::EVM::new();
         evm.env = self.env.clone();
+        // calls are not charged, like in geth
+        if read_only {
+            evm.env.block.basefee = U256::zero();
+        }
         evm.env.tx = TxEnv {
-            caller: tx.from.unwrap_or_default(),
-            transact_to: match tx.to {
-                Some(NameOrAddress::Address(inner)) => TransactTo::Call(inner),
-                Some(NameOrAddress::Name(_)) => panic!("not allowed"),
-                None => TransactTo::Create(CreateScheme::Create),
-            },
-            data: tx.data.clone().unwrap_or_default().0,
+            caller: tx.from().copied().unwrap_or_default(),
+            transact_to,
+            data: tx.data().cloned().unwrap_or_default().0,
             chain_id: Some(self.env.cfg.chain_id.as_u64()),
-            nonce: Some(tx.nonce.unwrap_or_default().as_u64()),
-            value: tx.value.unwrap_or_default(),
-            gas_price: tx.gas_price.unwrap_or_default(),
-            gas_priority_fee: Some(tx.gas_price.unwrap_or_default()),
-            gas_limit: tx.gas.unwrap_or_default().as_u64(),
-            access_list: vec![],
+            nonce: Some(nonce.as_u64()),
+            value: tx.value().copied().unwrap_or_default(),
+            gas_price,
+            gas_priority_fee,
+            gas_limit: gas_limit.as_u64(),
+            access_list: access_list
+                .into_iter()
+                .map(|item| {
+                    let slots = item
+                        .storage_keys
+                        .into_iter()
+                        .map(|slot| U256::from(slot.as_bytes()))
+                        .collect();
+                    (item.address, slots)
+                })
+                .collect(),
         };
         evm.database(&mut self.db);
 
         let (ret, out, gas, state, logs) = evm.transact();
         if !read_only {
+            self.changes.record(&state);
             self.db.commit(state);
         };
 
@@ -89,32 +302,169 @@ impl<Db: Database + DatabaseCommit> State<Db> {
     }
 }
 
+/// The address of a certificate's author: the last 20 bytes of the keccak256 hash of its public
+/// key, like Ethereum addresses. Empty keys map to the zero address.
+fn author_address(public_key: &[u8]) -> Address {
+    if public_key.is_empty() {
+        return Address::zero();
+    }
+    Address::from_slice(&ethers::utils::keccak256(public_key)[12..])
+}
+
+/// The `DeliverTx` response of a transaction which was not included in the block.
+fn rejected_tx(err: TxError) -> ResponseDeliverTx {
+    tracing::error!("rejected tx: {}", err);
+    ResponseDeliverTx {
+        code: err.code(),
+        log: err.to_string(),
+        codespace: CODESPACE.to_string(),
+        ..Default::default()
+    }
+}
+
+/// The ABCI events of an executed transaction, so that they can be indexed: a `tx` event with
+/// its hash, sender, recipient and deployed contract, and a `log` event with the address,
+/// topics and data of each EVM log.
+fn tx_events(hash: H256, from: Address, result: &TransactionResult) -> Vec<Event> {
+    let attribute = |key: &str, value: String, index: bool| EventAttribute {
+        key: key.into(),
+        value: value.into(),
+        index,
+    };
+
+    let mut tx = vec![
+        attribute("hash", format!("{:?}", hash), true),
+        attribute("from", format!("{:?}", from), true),
+    ];
+    if let Some(NameOrAddress::Address(to)) = result.transaction.to() {
+        tx.push(attribute("to", format!("{:?}", to), true));
+    }
+    if let Some(address) = result.contract_address() {
+        tx.push(attribute(
+            "contract_address",
+            format!("{:?}", address),
+            true,
+        ));
+    }
+
+    let mut events = vec![Event {
+        r#type: "tx".to_string(),
+        attributes: tx,
+    }];
+    events.extend(result.logs.iter().map(|log| {
+        let mut attributes = vec![attribute("address", format!("{:?}", log.address), true)];
+        attributes.extend(
+            log.topics
+                .iter()
+                .enumerate()
+                .map(|(i, topic)| attribute(&format!("topic{}", i), format!("{:?}", topic), true)),
+        );
+        attributes.push(attribute(
+            "data",
+            format!("0x{}", hex::encode(&log.data)),
+            false,
+        ));
+        Event {
+            r#type: "log".to_string(),
+            attributes,
+        }
+    }));
+    events
+}
+
 pub struct Consensus<Db> {
     pub committed_state: Arc<Mutex<State<Db>>>,
     pub current_state: Arc<Mutex<State<Db>>>,
+    /// The state used by the [`Mempool`] to validate transactions, reset on every commit.
+    pub check_state: Arc<Mutex<CheckState<Db>>>,
+    /// The states committed before the latest one, shared with [`Info`]
+    pub versions: Arc<Mutex<StateVersions<Db>>>,
 }
 
 impl<Db: Clone> Consensus<Db> {
     pub fn new(state: State<Db>) -> Self {
         let committed_state = Arc::new(Mutex::new(state.clone()));
+        let check_state = Arc::new(Mutex::new(CheckState::new(state.clone())));
         let current_state = Arc::new(Mutex::new(state));
 
         Consensus {
             committed_state,
             current_state,
+            check_state,
+            versions: Default::default(),
         }
     }
 }
 
 #[async_trait]
-impl<Db: Clone + Send + Sync + DatabaseCommit + Database> ConsensusTrait for Consensus<Db> {
+impl<Db: Clone + Send + Sync + DatabaseCommit + Database + Persist + GenesisDb> ConsensusTrait
+    for Consensus<Db>
+{
     #[tracing::instrument(skip(self))]
-    async fn init_chain(&self, _init_chain_request: RequestInitChain) -> ResponseInitChain {
-        ResponseInitChain::default()
+    async fn init_chain(&self, init_chain_request: RequestInitChain) -> ResponseInitChain {
+        let mut current_state = self.current_state.lock().await;
+
+        let genesis = match init_chain_request.app_state_bytes.as_slice() {
+            [] => Ok(Genesis::default()),
+            genesis => Genesis::from_json(genesis),
+        };
+        // the Engine calls InitChain on every start, but the accounts only get written on a new
+        // chain
+        let res = genesis.and_then(|genesis| {
+            if current_state.block_height > 0 {
+                current_state.apply_chain_config(&genesis)
+            } else {
+                current_state.apply_genesis(&genesis)?;
+                current_state.app_hash = current_state.state_root().as_bytes().to_vec();
+                Ok(())
+            }
+        });
+        // the app cannot run a chain it does not agree on with the other nodes
+        if let Err(err) = res {
+            panic!("invalid genesis: {:?}", err);
+        }
+
+        *self.check_state.lock().await = CheckState::new(current_state.clone());
+        *self.committed_state.lock().await = current_state.clone();
+
+        ResponseInitChain {
+            app_hash: current_state.app_hash.clone(),
+            ..Default::default()
+        }
     }
 
     #[tracing::instrument(skip(self))]
-    async fn begin_block(&self, _begin_block_request: RequestBeginBlock) -> ResponseBeginBlock {
+    async fn begin_block(&self, begin_block_request: RequestBeginBlock) -> ResponseBeginBlock {
+        let mut current_state = self.current_state.lock().await;
+        current_state.pending_block = PendingBlock::default();
+
+        // the Engine sends the certificate's digest as the block hash, which makes a
+        // deterministic source of randomness for `block.difficulty`
+        let header = begin_block_request.header.unwrap_or_default();
+        let mut digest = [0u8; 32];
+        let len = std::cmp::min(begin_block_request.hash.len(), 32);
+        digest[..len].copy_from_slice(&begin_block_request.hash[..len]);
+
+        let parent_timestamp = current_state.latest_block.block.timestamp.as_u64();
+        let timestamp = header
+            .time
+            .map(|time| time.seconds.max(0) as u64)
+            .unwrap_or_default();
+        let coinbase = match current_state.gas_config.coinbase {
+            Some(coinbase) => coinbase,
+            None => author_address(&header.proposer_address),
+        };
+
+        let spec_id = current_state.chain_spec.spec_at(header.height as u64);
+        current_state.env.cfg.spec_id = spec_id;
+
+        let block = &mut current_state.env.block;
+        block.number = header.height.into();
+        // timestamps never go back, even if certificates of older rounds get committed late
+        block.timestamp = std::cmp::max(timestamp, parent_timestamp).into();
+        block.difficulty = U256::from_big_endian(&digest);
+        block.coinbase = coinbase;
+
         ResponseBeginBlock::default()
     }
 
@@ -123,30 +473,37 @@ impl<Db: Clone + Send + Sync + DatabaseCommit + Database> ConsensusTrait for Con
         tracing::trace!("delivering tx");
         let mut state = self.current_state.lock().await;
 
-        let mut tx: TransactionRequest = match serde_json::from_slice(&deliver_tx_request.tx) {
-            Ok(tx) => tx,
-            Err(_) => {
-                tracing::error!("could not decode request");
-                return ResponseDeliverTx {
-                    data: "could not decode request".into(),
-                    ..Default::default()
-                };
-            }
+        let chain_id = state.env.cfg.chain_id;
+        let signed = match SignedTransaction::decode(&deliver_tx_request.tx, chain_id)
+            .and_then(|signed| state.validate(&signed).map(|_| signed))
+        {
+            Ok(signed) => signed,
+            Err(err) => return rejected_tx(err),
         };
 
-        // resolve the `to`
-        match tx.to {
-            Some(NameOrAddress::Address(addr)) => tx.to = Some(addr.into()),
-            _ => panic!("not an address"),
+        let (hash, from) = (signed.hash, signed.from);
+        let gas_wanted = signed.tx.gas().copied().unwrap_or_default().low_u64();
+        let result = match state.execute(signed.tx, false).await {
+            Ok(result) => result,
+            Err(err) => return rejected_tx(err),
         };
-
-        let result = state.execute(tx, false).await.unwrap();
+        state.pending_block.push(hash, from, &result);
         tracing::trace!("executed tx");
 
-        ResponseDeliverTx {
-            data: serde_json::to_vec(&result).unwrap(),
+        let mut res = ResponseDeliverTx {
+            data: serde_json::to_vec(&result).unwrap_or_default(),
+            gas_wanted: gas_wanted as i64,
+            gas_used: result.gas as i64,
+            events: tx_events(hash, from, &result),
             ..Default::default()
+        };
+        // failed txs are still included, but flagged with a non-zero code
+        if let Some(err) = ExecutionError::from_exit(result.exit) {
+            res.code = err.code();
+            res.log = err.to_string();
+            res.codespace = CODESPACE.to_string();
         }
+        res
     }
 
     #[tracing::instrument(skip(self))]
@@ -154,7 +511,19 @@ impl<Db: Clone + Send + Sync + DatabaseCommit + Database> ConsensusTrait for Con
         tracing::trace!("ending block");
         let mut current_state = self.current_state.lock().await;
         current_state.block_height = end_block_request.height;
-        current_state.app_hash = vec![];
+        let state_root = current_state.state_root();
+        current_state.app_hash = state_root.as_bytes().to_vec();
+
+        let pending_block = std::mem::take(&mut current_state.pending_block);
+        current_state.latest_block = pending_block.seal(
+            &current_state.latest_block.block,
+            end_block_request.height as u64,
+            &current_state.env.block,
+            state_root,
+        );
+        current_state.env.block.basefee = current_state
+            .gas_config
+            .next_base_fee(&current_state.latest_block.block);
         tracing::trace!("done");
 
         ResponseEndBlock::default()
@@ -163,97 +532,493 @@ impl<Db: Clone + Send + Sync + DatabaseCommit + Database> ConsensusTrait for Con
     #[tracing::instrument(skip(self))]
     async fn commit(&self, _commit_request: RequestCommit) -> ResponseCommit {
         tracing::trace!("taking lock");
-        let current_state = self.current_state.lock().await.clone();
+        let (current_state, changes) = {
+            let mut current_state = self.current_state.lock().await;
+            // the changes get persisted with this commit, the next block starts without any
+            let changes = std::mem::take(&mut current_state.changes);
+            (current_state.clone(), changes)
+        };
+        self.check_state
+            .lock()
+            .await
+            .reset(current_state.clone())
+            .await;
         let mut committed_state = self.committed_state.lock().await;
-        *committed_state = current_state;
+        // keep the previous state around for historical queries
+        let previous = std::mem::replace(&mut *committed_state, current_state);
+        self.versions.lock().await.insert(previous.version());
+        // the block goes first, so that it gets overwritten if we crash before persisting the
+        // state and the block gets replayed. A commit which is not stored must not be
+        // acknowledged, since the Engine would not replay it, so failures abort the app.
+        if let Err(err) = committed_state
+            .history
+            .insert_block(&committed_state.latest_block)
+        {
+            panic!("could not store block: {:?}", err);
+        }
+        if let Err(err) = committed_state.db.persist(
+            &changes,
+            committed_state.block_height,
+            &committed_state.trie,
+        ) {
+            panic!("could not persist state: {:?}", err);
+        }
         tracing::trace!("committed");
 
         ResponseCommit {
-            data: vec![], // (*committed_state).app_hash.clone(),
+            data: committed_state.app_hash.clone(),
             retain_height: 0,
         }
     }
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct Mempool;
+#[derive(Debug, Clone)]
+pub struct Mempool<Db> {
+    /// The committed state plus all transactions accepted since the last commit.
+    pub state: Arc<Mutex<CheckState<Db>>>,
+}
 
 #[async_trait]
-impl MempoolTrait for Mempool {
-    async fn check_tx(&self, _check_tx_request: RequestCheckTx) -> ResponseCheckTx {
-        ResponseCheckTx::default()
+impl<Db: Send + Sync + Database + DatabaseCommit> MempoolTrait for Mempool<Db> {
+    #[tracing::instrument(skip(self))]
+    async fn check_tx(&self, check_tx_request: RequestCheckTx) -> ResponseCheckTx {
+        self.state.lock().await.check(check_tx_request.tx).await
+    }
+}
+
+/// The state which transactions are checked against: the committed state, plus the
+/// transactions accepted since then which were not committed yet.
+#[derive(Debug, Clone)]
+pub struct CheckState<Db> {
+    pub state: State<Db>,
+    /// The raw transactions which were accepted but not committed yet
+    pub pending: Vec<Vec<u8>>,
+}
+
+impl<Db> CheckState<Db> {
+    pub fn new(state: State<Db>) -> Self {
+        Self {
+            state,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<Db: Database + DatabaseCommit> CheckState<Db> {
+    /// Validates the transaction and applies it if it is accepted, so that the sender's next
+    /// transaction is checked against the new nonce and balance.
+    pub async fn check(&mut self, tx: Vec<u8>) -> ResponseCheckTx {
+        let res = check_tx(&mut self.state, &tx).await;
+        if res.code == 0 {
+            self.pending.push(tx);
+        }
+        res
+    }
+
+    /// Moves on to a newly committed state, re-applying the pending transactions on top of it.
+    /// Those which got committed are dropped as replays, along with those which are no longer
+    /// valid, while the others stay pending so that the senders' follow-up transactions are
+    /// still accepted.
+    pub async fn reset(&mut self, state: State<Db>) {
+        self.state = state;
+        for tx in std::mem::take(&mut self.pending) {
+            let res = self.check(tx).await;
+            if res.code != 0 {
+                tracing::trace!("dropped pending tx: {}", res.log);
+            }
+        }
+    }
+}
+
+async fn check_tx<Db: Database + DatabaseCommit>(
+    state: &mut State<Db>,
+    tx: &[u8],
+) -> ResponseCheckTx {
+    let chain_id = state.env.cfg.chain_id;
+    let tx = match SignedTransaction::decode(tx, chain_id)
+        .and_then(|signed| state.validate(&signed).map(|_| signed.tx))
+    {
+        Ok(tx) => tx,
+        Err(err) => {
+            tracing::debug!("rejected tx: {}", err);
+            return ResponseCheckTx {
+                code: err.code(),
+                log: err.to_string(),
+                info: format!("{:?}", err),
+                codespace: CODESPACE.to_string(),
+                ..Default::default()
+            };
+        }
+    };
+    let gas_wanted = tx.gas().copied().unwrap_or_default().low_u64() as i64;
+
+    let gas_used = match state.execute(tx, false).await {
+        Ok(result) => result.gas as i64,
+        Err(err) => {
+            tracing::debug!("rejected tx: {}", err);
+            return ResponseCheckTx {
+                code: err.code(),
+                log: err.to_string(),
+                info: format!("{:?}", err),
+                codespace: CODESPACE.to_string(),
+                ..Default::default()
+            };
+        }
+    };
+
+    ResponseCheckTx {
+        gas_wanted,
+        gas_used,
+        ..Default::default()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Info<Db> {
+    /// The latest committed state
     pub state: Arc<Mutex<State<Db>>>,
+    /// The states committed at the previous heights
+    pub versions: Arc<Mutex<StateVersions<Db>>>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 #[allow(clippy::large_enum_variant)]
 pub enum Query {
     EthCall(TransactionRequest),
+    /// The lowest gas limit with which the transaction succeeds
+    EstimateGas(TransactionRequest),
     Balance(Address),
+    Nonce(Address),
+    Code(Address),
+    Storage(Address, H256),
+    /// The account's leaf in the state trie
+    Account(Address),
+    /// The EIP-1186 proof of the account and of the provided storage slots
+    Proof(Address, Vec<H256>),
+    BlockNumber,
+    ChainId,
+    BlockByNumber(u64),
+    BlockByHash(H256),
+    Transaction(H256),
+    Receipt(H256),
+    BaseFee,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 #[allow(clippy::large_enum_variant)]
 pub enum QueryResponse {
     Tx(TransactionResult),
+    Gas(U256),
     Balance(U256),
+    Nonce(U256),
+    Code(Bytes),
+    Storage(H256),
+    Account(Account),
+    Proof(AccountProof),
+    BlockNumber(U64),
+    ChainId(U64),
+    Block(Option<Block>),
+    Transaction(Option<BlockTransaction>),
+    Receipt(Option<Receipt>),
+    BaseFee(U256),
 }
 
 impl QueryResponse {
-    pub fn as_tx(&self) -> &TransactionResult {
+    pub fn as_tx(&self) -> Result<&TransactionResult, QueryError> {
+        match self {
+            QueryResponse::Tx(inner) => Ok(inner),
+            res => Err(QueryError::UnexpectedResponse(format!("{:?}", res))),
+        }
+    }
+
+    pub fn as_balance(&self) -> Result<U256, QueryError> {
         match self {
-            QueryResponse::Tx(inner) => inner,
-            _ => panic!("not a tx"),
+            QueryResponse::Balance(inner) => Ok(*inner),
+            res => Err(QueryError::UnexpectedResponse(format!("{:?}", res))),
         }
     }
 
-    pub fn as_balance(&self) -> U256 {
+    pub fn as_gas(&self) -> Result<U256, QueryError> {
         match self {
-            QueryResponse::Balance(inner) => *inner,
-            _ => panic!("not a balance"),
+            QueryResponse::Gas(inner) => Ok(*inner),
+            res => Err(QueryError::UnexpectedResponse(format!("{:?}", res))),
         }
     }
 }
 
-#[async_trait]
-impl<Db: Send + Sync + Database + DatabaseCommit> InfoTrait for Info<Db> {
-    // replicate the eth_call interface
-    async fn query(&self, query_request: RequestQuery) -> ResponseQuery {
-        let mut state = self.state.lock().await;
+/// Errors returned to the client when a query cannot be served. The ABCI API only forwards the
+/// response's `value`, so the error is also sent as the value, as a [`QueryFailure`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// The payload is not a valid JSON-encoded [`Query`].
+    Decode(String),
+    /// The `eth_call` transaction cannot be executed.
+    Tx(TxError),
+    /// The transaction whose gas is estimated fails even with the highest gas limit. The output
+    /// holds the revert reason, if any.
+    Execution {
+        error: ExecutionError,
+        output: Bytes,
+    },
+    /// The blocks could not be read from storage.
+    History(String),
+    /// The response is not of the kind the client expected.
+    UnexpectedResponse(String),
+    /// The state at the requested height was pruned, or committed before the app started.
+    PrunedHeight { height: i64, earliest: i64 },
+    /// The requested height is not committed yet.
+    FutureHeight { height: i64, latest: i64 },
+    /// The state trie could not be read.
+    Trie(String),
+}
 
-        let query: Query = match serde_json::from_slice(&query_request.data) {
-            Ok(tx) => tx,
-            // no-op just logger
-            Err(_) => {
-                return ResponseQuery {
-                    value: "could not decode request".into(),
-                    ..Default::default()
-                };
+impl QueryError {
+    /// The ABCI response code for this error, which follows the [`TxError`] ones.
+    pub fn code(&self) -> u32 {
+        match self {
+            QueryError::Decode(_) => TxError::Decode(String::new()).code(),
+            QueryError::Tx(err) => err.code(),
+            QueryError::Execution { error, .. } => error.code(),
+            QueryError::History(_) => 15,
+            QueryError::UnexpectedResponse(_) => 16,
+            QueryError::PrunedHeight { .. } => 17,
+            QueryError::FutureHeight { .. } => 18,
+            QueryError::Trie(_) => 19,
+        }
+    }
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Decode(err) => write!(f, "could not decode query: {}", err),
+            QueryError::Tx(err) => write!(f, "{}", err),
+            QueryError::Execution { error, output } if output.is_empty() => write!(f, "{}", error),
+            QueryError::Execution { error, output } => {
+                write!(f, "{}: 0x{}", error, hex::encode(output))
             }
+            QueryError::History(err) => write!(f, "could not read history: {}", err),
+            QueryError::UnexpectedResponse(res) => write!(f, "unexpected response: {}", res),
+            QueryError::PrunedHeight { height, earliest } => write!(
+                f,
+                "state at height {} is not available, the earliest retained height is {}",
+                height, earliest
+            ),
+            QueryError::FutureHeight { height, latest } => write!(
+                f,
+                "height {} is not committed yet, the latest height is {}",
+                height, latest
+            ),
+            QueryError::Trie(err) => write!(f, "could not read the state trie: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// The JSON value of a failed query's response, so that clients can tell it apart from a
+/// [`QueryResponse`] and get the error's code and revert data.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QueryFailure {
+    pub code: u32,
+    pub message: String,
+    /// The output of the reverted transaction, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Bytes>,
+}
+
+impl From<&QueryError> for QueryFailure {
+    fn from(err: &QueryError) -> Self {
+        let data = match err {
+            QueryError::Execution { output, .. } if !output.is_empty() => Some(output.clone()),
+            _ => None,
         };
+        QueryFailure {
+            code: err.code(),
+            message: err.to_string(),
+            data,
+        }
+    }
+}
+
+impl From<QueryError> for ResponseQuery {
+    fn from(err: QueryError) -> Self {
+        ResponseQuery {
+            code: err.code(),
+            log: err.to_string(),
+            codespace: CODESPACE.to_string(),
+            value: serde_json::to_vec(&QueryFailure::from(&err)).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
 
+impl<Db: Database + DatabaseCommit> State<Db> {
+    /// Answers the query from this state.
+    async fn answer(&mut self, query: Query) -> Result<QueryResponse, QueryError> {
         let res = match query {
-            Query::EthCall(mut tx) => {
-                match tx.to {
-                    Some(NameOrAddress::Address(addr)) => tx.to = Some(addr.into()),
-                    _ => panic!("not an address"),
+            Query::EthCall(tx) => match self.execute(tx.into(), true).await {
+                Ok(result) => QueryResponse::Tx(result),
+                Err(err) => return Err(QueryError::Tx(err)),
+            },
+            Query::EstimateGas(tx) => QueryResponse::Gas(self.estimate_gas(tx).await?),
+            Query::Balance(address) => QueryResponse::Balance(self.db.basic(address).balance),
+            Query::Nonce(address) => QueryResponse::Nonce(self.db.basic(address).nonce.into()),
+            Query::Code(address) => {
+                let info = self.db.basic(address);
+                let code = match info.code {
+                    Some(code) => code,
+                    None => self.db.code_by_hash(info.code_hash),
                 };
+                // the bytecode may be padded after analysis, so only keep the original length
+                QueryResponse::Code(code.bytes().slice(..code.len()).into())
+            }
+            Query::Storage(address, slot) => {
+                let value = self.db.storage(address, U256::from(slot.as_bytes()));
+                let mut buf = [0u8; 32];
+                value.to_big_endian(&mut buf);
+                QueryResponse::Storage(H256::from(buf))
+            }
+            Query::Account(address) => match self.trie.account(address) {
+                Ok(account) => QueryResponse::Account(account),
+                Err(err) => return Err(QueryError::Trie(err.to_string())),
+            },
+            Query::Proof(address, slots) => match self.trie.proof(address, &slots) {
+                Ok(proof) => QueryResponse::Proof(proof),
+                Err(err) => return Err(QueryError::Trie(err.to_string())),
+            },
+            Query::BlockNumber => QueryResponse::BlockNumber((self.block_height as u64).into()),
+            Query::ChainId => QueryResponse::ChainId(self.env.cfg.chain_id.as_u64().into()),
+            Query::BaseFee => QueryResponse::BaseFee(self.env.block.basefee),
+            Query::BlockByNumber(number) => match self.history.block_by_number(number) {
+                Ok(block) => QueryResponse::Block(block),
+                Err(err) => return Err(QueryError::History(err.to_string())),
+            },
+            Query::BlockByHash(hash) => match self.history.block_by_hash(hash) {
+                Ok(block) => QueryResponse::Block(block),
+                Err(err) => return Err(QueryError::History(err.to_string())),
+            },
+            Query::Transaction(hash) => match self.history.transaction(hash) {
+                Ok(tx) => QueryResponse::Transaction(tx),
+                Err(err) => return Err(QueryError::History(err.to_string())),
+            },
+            Query::Receipt(hash) => match self.history.receipt(hash) {
+                Ok(receipt) => QueryResponse::Receipt(receipt),
+                Err(err) => return Err(QueryError::History(err.to_string())),
+            },
+        };
+
+        Ok(res)
+    }
+}
+
+impl<Db: Database + DatabaseCommit> State<Db> {
+    /// The lowest gas limit with which the transaction succeeds, found by binary search between
+    /// the gas it uses and its own gas limit, or the block gas limit if it has none. The gas
+    /// used is not always enough, e.g. because of refunds or of the 63/64 rule of calls. The
+    /// transaction is executed without fees, so the sender does not need to pay for the gas.
+    async fn estimate_gas(&mut self, mut tx: TransactionRequest) -> Result<U256, QueryError> {
+        tx.gas_price = None;
+        let cap = tx.gas.unwrap_or(self.env.block.gas_limit);
+        tx.gas = Some(cap);
+        let result = self
+            .execute(tx.clone().into(), true)
+            .await
+            .map_err(QueryError::Tx)?;
+        if let Some(error) = ExecutionError::from_exit(result.exit) {
+            let output = match result.out {
+                TransactOut::Call(out) | TransactOut::Create(out, _) => out.into(),
+                TransactOut::None => Bytes::default(),
+            };
+            return Err(QueryError::Execution { error, output });
+        }
+
+        // `lo` always fails and `hi` always succeeds: a gas limit below the gas used cannot
+        // succeed, and `execute` rejects the caps which do not fit in 64 bits
+        let mut lo = result.gas.saturating_sub(1);
+        let mut hi = cap.as_u64();
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if self.succeeds(&tx, mid).await {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Ok(hi.into())
+    }
+
+    async fn succeeds(&mut self, tx: &TransactionRequest, gas: u64) -> bool {
+        let tx = tx.clone().gas(gas);
+        match self.execute(tx.into(), true).await {
+            Ok(result) => ExecutionError::from_exit(result.exit).is_none(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// The state a query is answered from, copied out of [`Info`]'s locks.
+struct QuerySnapshot<Db> {
+    /// The queried height, resolved to the latest committed one for height 0
+    height: i64,
+    state: Result<State<Db>, QueryError>,
+}
 
-                let result = state.execute(tx, true).await.unwrap();
-                QueryResponse::Tx(result)
+impl<Db: Persist> Info<Db> {
+    /// Copies the state committed at `height`, or at the latest height for 0, releasing the
+    /// locks before the query gets executed. The copy reads from the committed trie instead of
+    /// copying the cache, so it is cheap to make under the locks.
+    async fn snapshot(&self, height: i64) -> QuerySnapshot<Db> {
+        let latest = self.state.lock().await;
+        let height = match height {
+            0 => latest.block_height,
+            height => height,
+        };
+        let state = match height.cmp(&latest.block_height) {
+            Ordering::Equal => Ok(latest.version()),
+            Ordering::Greater => Err(QueryError::FutureHeight {
+                height,
+                latest: latest.block_height,
+            }),
+            Ordering::Less => {
+                let versions = self.versions.lock().await;
+                let earliest = versions.earliest().unwrap_or(latest.block_height);
+                versions
+                    .get(height)
+                    .map(State::version)
+                    .ok_or(QueryError::PrunedHeight { height, earliest })
             }
-            Query::Balance(address) => QueryResponse::Balance(state.db.basic(address).balance),
         };
+        QuerySnapshot { height, state }
+    }
+}
 
-        ResponseQuery {
-            key: query_request.data,
-            value: serde_json::to_vec(&res).unwrap(),
-            ..Default::default()
+#[async_trait]
+impl<Db: Send + Sync + Database + DatabaseCommit + Persist> InfoTrait for Info<Db> {
+    // replicate the eth_call interface
+    async fn query(&self, query_request: RequestQuery) -> ResponseQuery {
+        let query: Query = match serde_json::from_slice(&query_request.data) {
+            Ok(query) => query,
+            Err(err) => return QueryError::Decode(err.to_string()).into(),
+        };
+
+        // the queries run on a snapshot of the state, so that slow ones, e.g. gas estimations,
+        // neither block each other nor the next commit
+        let snapshot = self.snapshot(query_request.height).await;
+        let height = snapshot.height;
+        let res = match snapshot.state {
+            Ok(mut state) => state.answer(query).await,
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(res) => ResponseQuery {
+                key: query_request.data,
+                value: serde_json::to_vec(&res).unwrap_or_default(),
+                height,
+                ..Default::default()
+            },
+            Err(err) => err.into(),
         }
     }
 
@@ -278,21 +1043,40 @@ impl SnapshotTrait for Snapshot {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tx::sign;
     // use ethers::prelude::*;
 
+    /// A state without base fee, for the tests which do not care about fees.
+    fn fee_free_state() -> State<CacheDB<MemoryDb>> {
+        let mut state = State::default();
+        state.set_gas_config(GasConfig {
+            initial_base_fee: U256::zero(),
+            ..Default::default()
+        });
+        state
+    }
+
     #[tokio::test]
     async fn run_and_query_tx() {
         let val = ethers::utils::parse_units(1, 18).unwrap();
-        let alice = Address::random();
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let alice = wallet.address();
         let bob = Address::random();
+        let coinbase = Address::random();
 
         let mut state = State::default();
+        state.set_gas_config(GasConfig {
+            coinbase: Some(coinbase),
+            ..Default::default()
+        });
 
-        // give alice some money
-        state.db.insert_account_info(
+        // give alice some money, and enough to pay for the gas
+        let base_fee = U256::from(crate::gas::INITIAL_BASE_FEE);
+        let gas_price = base_fee * 2;
+        state.insert_account_info(
             alice,
             revm::AccountInfo {
-                balance: val,
+                balance: val + gas_price * 31000,
                 ..Default::default()
             },
         );
@@ -301,26 +1085,32 @@ mod tests {
         let tx = TransactionRequest::new()
             .from(alice)
             .to(bob)
-            .gas_price(0)
+            .gas_price(gas_price)
             .data(vec![1, 2, 3, 4, 5])
             .gas(31000)
-            .value(val);
+            .value(val)
+            .nonce(0)
+            .chain_id(state.env.cfg.chain_id.as_u64());
 
         // Send it over an ABCI message
 
         let consensus = Consensus::new(state);
 
         let req = RequestDeliverTx {
-            tx: serde_json::to_vec(&tx).unwrap(),
+            tx: sign(&wallet, tx),
         };
         let res = consensus.deliver_tx(req).await;
         let res: TransactionResult = serde_json::from_slice(&res.data).unwrap();
         // tx passed
         assert_eq!(res.exit, Return::Stop);
+        let gas_used = U256::from(res.gas);
+        // the queries read the state trie, which gets updated at the end of the block
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
 
         // now we query the state for bob's balance
         let info = Info {
             state: consensus.current_state.clone(),
+            versions: Default::default(),
         };
         let res = info
             .query(RequestQuery {
@@ -329,7 +1119,1001 @@ mod tests {
             })
             .await;
         let res: QueryResponse = serde_json::from_slice(&res.value).unwrap();
-        let balance = res.as_balance();
+        let balance = res.as_balance().unwrap();
         assert_eq!(balance, val);
+
+        // the base fee is burnt and the rest goes to the coinbase
+        let mut state = consensus.current_state.lock().await;
+        assert_eq!(
+            state.db.basic(alice).balance,
+            gas_price * 31000 - gas_price * gas_used
+        );
+        assert_eq!(
+            state.db.basic(coinbase).balance,
+            (gas_price - base_fee) * gas_used
+        );
+    }
+
+    #[tokio::test]
+    async fn duplicated_tx_executes_once() {
+        let val = ethers::utils::parse_units(1, 18).unwrap();
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let alice = wallet.address();
+        let bob = Address::random();
+
+        let mut state = fee_free_state();
+        state.insert_account_info(
+            alice,
+            revm::AccountInfo {
+                balance: val * 2,
+                ..Default::default()
+            },
+        );
+        let consensus = Consensus::new(state);
+
+        let tx = TransactionRequest::new()
+            .from(alice)
+            .to(bob)
+            .gas_price(0)
+            .gas(21000)
+            .value(val)
+            .nonce(0)
+            .chain_id(1u64);
+        let raw = sign(&wallet, tx.clone());
+
+        // the same tx gets included twice, e.g. by two different workers
+        let res = consensus
+            .deliver_tx(RequestDeliverTx { tx: raw.clone() })
+            .await;
+        assert_eq!(res.code, 0);
+        let res = consensus.deliver_tx(RequestDeliverTx { tx: raw }).await;
+        assert_eq!(
+            res.code,
+            TxError::NonceTooLow {
+                expected: 1.into(),
+                got: 0.into()
+            }
+            .code()
+        );
+
+        // skipping a nonce is not allowed either
+        let res = consensus
+            .deliver_tx(RequestDeliverTx {
+                tx: sign(&wallet, tx.nonce(2)),
+            })
+            .await;
+        assert_eq!(
+            res.code,
+            TxError::NonceTooHigh {
+                expected: 1.into(),
+                got: 2.into()
+            }
+            .code()
+        );
+
+        let mut state = consensus.current_state.lock().await;
+        assert_eq!(state.db.basic(bob).balance, val);
+        assert_eq!(state.db.basic(alice).nonce, 1);
+    }
+
+    #[tokio::test]
+    async fn check_tx_tracks_pending_nonces() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let consensus = Consensus::new(fee_free_state());
+        let mempool = Mempool {
+            state: consensus.check_state.clone(),
+        };
+
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .gas_price(0)
+            .gas(21000)
+            .nonce(0)
+            .chain_id(1u64);
+        let check = |tx: TransactionRequest| RequestCheckTx {
+            tx: sign(&wallet, tx),
+            ..Default::default()
+        };
+
+        // consecutive nonces are accepted before being committed
+        assert_eq!(mempool.check_tx(check(tx.clone())).await.code, 0);
+        assert_eq!(mempool.check_tx(check(tx.clone().nonce(1))).await.code, 0);
+
+        // but replays and gaps are not
+        let res = mempool.check_tx(check(tx.clone())).await;
+        assert_eq!(
+            res.code,
+            TxError::NonceTooLow {
+                expected: 2.into(),
+                got: 0.into()
+            }
+            .code()
+        );
+        let res = mempool.check_tx(check(tx.nonce(5))).await;
+        assert_eq!(
+            res.code,
+            TxError::NonceTooHigh {
+                expected: 2.into(),
+                got: 5.into()
+            }
+            .code()
+        );
+    }
+
+    #[tokio::test]
+    async fn check_tx_keeps_pending_txs_across_commits() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let consensus = Consensus::new(fee_free_state());
+        let mempool = Mempool {
+            state: consensus.check_state.clone(),
+        };
+
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .gas_price(0)
+            .gas(21000)
+            .nonce(0)
+            .chain_id(1u64);
+        let check = |tx: TransactionRequest| RequestCheckTx {
+            tx: sign(&wallet, tx),
+            ..Default::default()
+        };
+        let first = check(tx.clone());
+        let second = check(tx.clone().nonce(1));
+        assert_eq!(mempool.check_tx(first.clone()).await.code, 0);
+        assert_eq!(mempool.check_tx(second.clone()).await.code, 0);
+
+        // only the first tx makes it into the block
+        consensus.begin_block(RequestBeginBlock::default()).await;
+        consensus
+            .deliver_tx(RequestDeliverTx { tx: first.tx })
+            .await;
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        consensus.commit(RequestCommit::default()).await;
+
+        // the second one is still pending, so the sender can go on with the next nonce
+        assert_eq!(
+            consensus.check_state.lock().await.pending,
+            vec![second.tx.clone()]
+        );
+        assert_eq!(mempool.check_tx(check(tx.nonce(2))).await.code, 0);
+        let res = mempool.check_tx(second).await;
+        assert_eq!(
+            res.code,
+            TxError::NonceTooLow {
+                expected: 3.into(),
+                got: 1.into()
+            }
+            .code()
+        );
+    }
+
+    #[tokio::test]
+    async fn check_tx_rejects_unpayable_txs() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let base_fee = U256::from(crate::gas::INITIAL_BASE_FEE);
+        let mut state = State::default();
+        state.insert_account_info(
+            wallet.address(),
+            revm::AccountInfo {
+                balance: base_fee * 21000,
+                ..Default::default()
+            },
+        );
+        let consensus = Consensus::new(state);
+        let mempool = Mempool {
+            state: consensus.check_state.clone(),
+        };
+
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .gas_price(base_fee)
+            .gas(21000)
+            .nonce(0)
+            .chain_id(1u64);
+        let check = |tx: TransactionRequest| RequestCheckTx {
+            tx: sign(&wallet, tx),
+            ..Default::default()
+        };
+
+        let res = mempool.check_tx(check(tx.clone().gas(0))).await;
+        assert_eq!(
+            res.code,
+            TxError::IntrinsicGasTooLow {
+                minimum: 21000.into(),
+                got: 0.into(),
+            }
+            .code()
+        );
+        assert_eq!(res.codespace, CODESPACE);
+
+        let res = mempool.check_tx(check(tx.clone().gas_price(1))).await;
+        assert_eq!(
+            res.code,
+            TxError::FeeCapTooLow {
+                base_fee,
+                got: 1.into(),
+            }
+            .code()
+        );
+
+        let res = mempool.check_tx(check(tx.clone().value(1))).await;
+        assert_eq!(
+            res.code,
+            TxError::InsufficientFunds {
+                balance: base_fee * 21000,
+                cost: base_fee * 21000 + 1,
+            }
+            .code()
+        );
+
+        // the balance exactly covers the gas
+        let res = mempool.check_tx(check(tx)).await;
+        assert_eq!(res.code, 0);
+        assert_eq!(res.gas_wanted, 21000);
+    }
+
+    #[tokio::test]
+    async fn app_hash_is_committed() {
+        let mut state = State::default();
+        state.insert_account_info(
+            Address::random(),
+            revm::AccountInfo {
+                balance: 1.into(),
+                ..Default::default()
+            },
+        );
+        let expected = state.state_root();
+        let consensus = Consensus::new(state);
+
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        let res = consensus.commit(RequestCommit::default()).await;
+        assert_eq!(res.data, expected.as_bytes());
+
+        let info = Info {
+            state: consensus.committed_state.clone(),
+            versions: Default::default(),
+        };
+        let res = info.info(RequestInfo::default()).await;
+        assert_eq!(res.last_block_height, 1);
+        assert_eq!(res.last_block_app_hash, expected.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn blocks_are_chained() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let consensus = Consensus::new(fee_free_state());
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .gas_price(0)
+            .gas(21000)
+            .nonce(0)
+            .chain_id(1u64);
+        let raw = sign(&wallet, tx);
+        let hash = H256(ethers::utils::keccak256(&raw));
+
+        consensus.begin_block(RequestBeginBlock::default()).await;
+        consensus.deliver_tx(RequestDeliverTx { tx: raw }).await;
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        consensus.commit(RequestCommit::default()).await;
+
+        consensus.begin_block(RequestBeginBlock::default()).await;
+        consensus.end_block(RequestEndBlock { height: 2 }).await;
+        consensus.commit(RequestCommit::default()).await;
+
+        let info = Info {
+            state: consensus.committed_state.clone(),
+            versions: Default::default(),
+        };
+        let block = |query: Query| {
+            let info = &info;
+            async move {
+                match query_info(info, query).await {
+                    QueryResponse::Block(block) => block,
+                    res => panic!("unexpected response {:?}", res),
+                }
+            }
+        };
+
+        let first = block(Query::BlockByNumber(1)).await.unwrap();
+        assert_eq!(first.transactions, vec![hash]);
+        assert_eq!(first.gas_used, 21000.into());
+        assert_eq!(first.hash, first.compute_hash());
+
+        let second = block(Query::BlockByNumber(2)).await.unwrap();
+        assert_eq!(second.parent_hash, first.hash);
+        assert!(second.transactions.is_empty());
+        assert_eq!(block(Query::BlockByHash(second.hash)).await, Some(second));
+        assert_eq!(block(Query::BlockByNumber(3)).await, None);
+    }
+
+    #[tokio::test]
+    async fn receipts_are_indexed_by_tx_hash() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let consensus = Consensus::new(fee_free_state());
+        let to = Address::random();
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .to(to)
+            .gas_price(0)
+            .gas(21000)
+            .nonce(0)
+            .chain_id(1u64);
+        let first = sign(&wallet, tx.clone());
+        let second = sign(&wallet, tx.nonce(1));
+
+        consensus.begin_block(RequestBeginBlock::default()).await;
+        consensus.deliver_tx(RequestDeliverTx { tx: first }).await;
+        consensus
+            .deliver_tx(RequestDeliverTx { tx: second.clone() })
+            .await;
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        consensus.commit(RequestCommit::default()).await;
+
+        let info = Info {
+            state: consensus.committed_state.clone(),
+            versions: Default::default(),
+        };
+        let hash = H256(ethers::utils::keccak256(&second));
+        let block_hash = consensus
+            .committed_state
+            .lock()
+            .await
+            .latest_block
+            .block
+            .hash;
+
+        let receipt = match query_info(&info, Query::Receipt(hash)).await {
+            QueryResponse::Receipt(Some(receipt)) => receipt,
+            res => panic!("unexpected response {:?}", res),
+        };
+        assert_eq!(receipt.status, 1.into());
+        assert_eq!(receipt.transaction_index, 1.into());
+        assert_eq!(receipt.block_number, 1.into());
+        assert_eq!(receipt.block_hash, block_hash);
+        assert_eq!(receipt.from, wallet.address());
+        assert_eq!(receipt.to, Some(to));
+        assert_eq!(receipt.gas_used, 21000.into());
+        assert_eq!(receipt.cumulative_gas_used, 42000.into());
+        assert_eq!(receipt.contract_address, None);
+
+        let tx = match query_info(&info, Query::Transaction(hash)).await {
+            QueryResponse::Transaction(Some(tx)) => tx,
+            res => panic!("unexpected response {:?}", res),
+        };
+        assert_eq!(tx.block_hash, block_hash);
+        assert_eq!(tx.transaction.nonce(), Some(&1.into()));
+
+        // unknown txs have no receipt yet
+        match query_info(&info, Query::Receipt(H256::random())).await {
+            QueryResponse::Receipt(None) => {}
+            res => panic!("unexpected response {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_accounts_and_proofs() {
+        let alice = Address::random();
+        let mut state = State::default();
+        state.insert_account_info(
+            alice,
+            revm::AccountInfo {
+                balance: 100.into(),
+                nonce: 3,
+                ..Default::default()
+            },
+        );
+        state.insert_account_storage(alice, 1.into(), 7.into());
+        // the trie gets updated at the end of each block
+        state.state_root();
+        let info = Info {
+            state: Arc::new(Mutex::new(state)),
+            versions: Default::default(),
+        };
+
+        let account = match query_info(&info, Query::Account(alice)).await {
+            QueryResponse::Account(account) => account,
+            res => panic!("unexpected response {:?}", res),
+        };
+        assert_eq!(account.balance, 100.into());
+        assert_eq!(account.nonce, 3.into());
+
+        let slot = H256::from_low_u64_be(1);
+        let proof = match query_info(&info, Query::Proof(alice, vec![slot])).await {
+            QueryResponse::Proof(proof) => proof,
+            res => panic!("unexpected response {:?}", res),
+        };
+        assert_eq!(proof.storage_hash, account.storage_hash);
+        assert_eq!(proof.storage_proof[0].key, slot);
+        assert_eq!(proof.storage_proof[0].value, 7.into());
+        assert!(!proof.account_proof.is_empty());
+    }
+
+    #[tokio::test]
+    async fn queries_past_heights() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let bob = Address::random();
+        let mut state = fee_free_state();
+        state.insert_account_info(
+            wallet.address(),
+            revm::AccountInfo {
+                balance: 100.into(),
+                ..Default::default()
+            },
+        );
+        let consensus = Consensus::new(state);
+        *consensus.versions.lock().await = StateVersions::new(1);
+        let info = Info {
+            state: consensus.committed_state.clone(),
+            versions: consensus.versions.clone(),
+        };
+
+        // send 1 wei to bob at every height
+        for height in 1..=3 {
+            let tx = TransactionRequest::new()
+                .from(wallet.address())
+                .to(bob)
+                .value(1)
+                .gas(21000)
+                .gas_price(0)
+                .nonce(height - 1)
+                .chain_id(1u64);
+            consensus.begin_block(RequestBeginBlock::default()).await;
+            let res = consensus
+                .deliver_tx(RequestDeliverTx {
+                    tx: sign(&wallet, tx),
+                })
+                .await;
+            assert_eq!(res.code, 0);
+            consensus.end_block(RequestEndBlock { height }).await;
+            consensus.commit(RequestCommit::default()).await;
+        }
+
+        let balance_at = |height: i64| {
+            info.query(RequestQuery {
+                data: serde_json::to_vec(&Query::Balance(bob)).unwrap(),
+                height,
+                ..Default::default()
+            })
+        };
+        let balance = |res: ResponseQuery| {
+            serde_json::from_slice::<QueryResponse>(&res.value)
+                .unwrap()
+                .as_balance()
+                .unwrap()
+        };
+        assert_eq!(balance(balance_at(0).await), 3.into());
+        assert_eq!(balance(balance_at(3).await), 3.into());
+        let res = balance_at(2).await;
+        assert_eq!(res.height, 2);
+        assert_eq!(balance(res), 2.into());
+
+        // only one height before the latest is retained
+        let res = balance_at(1).await;
+        assert_eq!(
+            res.code,
+            QueryError::PrunedHeight {
+                height: 1,
+                earliest: 2
+            }
+            .code()
+        );
+        let res = balance_at(4).await;
+        assert_eq!(
+            res.code,
+            QueryError::FutureHeight {
+                height: 4,
+                latest: 3
+            }
+            .code()
+        );
+    }
+
+    #[tokio::test]
+    async fn init_chain_applies_genesis_once() {
+        let alice = Address::random();
+        let genesis = serde_json::json!({
+            "config": { "chainId": 5 },
+            "alloc": { format!("{:?}", alice): { "balance": "100" } }
+        });
+        let init_chain = || RequestInitChain {
+            app_state_bytes: serde_json::to_vec(&genesis).unwrap(),
+            ..Default::default()
+        };
+        let consensus = Consensus::new(State::default());
+
+        let res = consensus.init_chain(init_chain()).await;
+        let mut state = consensus.committed_state.lock().await;
+        assert_eq!(res.app_hash, state.trie.root().as_bytes());
+        assert_eq!(state.env.cfg.chain_id, 5.into());
+        assert_eq!(state.db.basic(alice).balance, 100.into());
+        drop(state);
+
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        consensus.commit(RequestCommit::default()).await;
+        consensus
+            .current_state
+            .lock()
+            .await
+            .insert_account_info(alice, Default::default());
+
+        // restarting the engine does not reset the state
+        consensus.init_chain(init_chain()).await;
+        let mut state = consensus.current_state.lock().await;
+        assert_eq!(state.env.cfg.chain_id, 5.into());
+        assert_eq!(state.db.basic(alice).balance, 0.into());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "invalid genesis")]
+    async fn init_chain_rejects_invalid_genesis() {
+        let consensus = Consensus::new(State::default());
+        consensus
+            .init_chain(RequestInitChain {
+                app_state_bytes: b"{\"alloc\": 1}".to_vec(),
+                ..Default::default()
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn begin_block_sets_block_env() {
+        let consensus = Consensus::new(State::default());
+        let author = vec![7u8; 32];
+        let begin_block = |height: i64, seconds: i64| {
+            let mut header = Header {
+                height,
+                proposer_address: author.clone(),
+                ..Default::default()
+            };
+            header.time.get_or_insert_with(Default::default).seconds = seconds;
+            RequestBeginBlock {
+                hash: vec![1u8; 32],
+                header: Some(header),
+                ..Default::default()
+            }
+        };
+
+        consensus.begin_block(begin_block(1, 100)).await;
+        {
+            let state = consensus.current_state.lock().await;
+            assert_eq!(state.env.block.number, 1.into());
+            assert_eq!(state.env.block.timestamp, 100.into());
+            assert_eq!(state.env.block.coinbase, author_address(&author));
+            assert_eq!(
+                state.env.block.difficulty,
+                U256::from_big_endian(&[1u8; 32])
+            );
+        }
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        consensus.commit(RequestCommit::default()).await;
+
+        // a certificate from an older round does not make the time go back
+        consensus.begin_block(begin_block(2, 90)).await;
+        let state = consensus.current_state.lock().await;
+        assert_eq!(state.env.block.number, 2.into());
+        assert_eq!(state.env.block.timestamp, 100.into());
+    }
+
+    #[tokio::test]
+    async fn enforces_block_gas_limit() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let mut state = State::default();
+        state.set_gas_config(GasConfig {
+            block_gas_limit: 50_000,
+            initial_base_fee: 1.into(),
+            ..Default::default()
+        });
+        state.insert_account_info(
+            wallet.address(),
+            revm::AccountInfo {
+                balance: ethers::utils::parse_ether(1).unwrap(),
+                ..Default::default()
+            },
+        );
+        let consensus = Consensus::new(state);
+        // the full block raises the base fee a bit
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .gas_price(2)
+            .gas(21000)
+            .chain_id(1u64);
+        let deliver = |tx: TransactionRequest| RequestDeliverTx {
+            tx: sign(&wallet, tx),
+        };
+
+        let res = consensus.deliver_tx(deliver(tx.clone().gas(60_000))).await;
+        assert_eq!(
+            res.code,
+            TxError::GasLimitTooHigh {
+                maximum: 50_000.into(),
+                got: 60_000.into(),
+            }
+            .code()
+        );
+
+        consensus.begin_block(RequestBeginBlock::default()).await;
+        let res = consensus.deliver_tx(deliver(tx.clone().nonce(0))).await;
+        assert_eq!(res.code, 0);
+        let res = consensus.deliver_tx(deliver(tx.clone().nonce(1))).await;
+        assert_eq!(res.code, 0);
+        // only 8000 gas is left in the block
+        let res = consensus.deliver_tx(deliver(tx.clone().nonce(2))).await;
+        assert_eq!(
+            res.code,
+            TxError::BlockGasExhausted {
+                remaining: 8000.into(),
+                got: 21000.into(),
+            }
+            .code()
+        );
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        consensus.commit(RequestCommit::default()).await;
+
+        // but it fits in the next one
+        consensus.begin_block(RequestBeginBlock::default()).await;
+        let res = consensus.deliver_tx(deliver(tx.nonce(2))).await;
+        assert_eq!(res.code, 0);
+    }
+
+    #[tokio::test]
+    async fn failed_txs_return_codes() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let mut state = fee_free_state();
+        // `revert(0, 0)`
+        let reverter = Address::random();
+        // `log1(0, 0, 0x2a)`
+        let logger = Address::random();
+        for (address, code) in [(reverter, "60006000fd"), (logger, "602a60006000a100")] {
+            let code = hex::decode(code).unwrap();
+            state.insert_account_info(
+                address,
+                revm::AccountInfo {
+                    code_hash: H256(ethers::utils::keccak256(&code)),
+                    code: Some(revm::Bytecode::new_raw(code.into())),
+                    ..Default::default()
+                },
+            );
+        }
+        let consensus = Consensus::new(state);
+        let tx = |to: Address, nonce: u64, gas: u64| RequestDeliverTx {
+            tx: sign(
+                &wallet,
+                TransactionRequest::new()
+                    .from(wallet.address())
+                    .to(to)
+                    .gas(gas)
+                    .gas_price(0)
+                    .nonce(nonce)
+                    .chain_id(1u64),
+            ),
+        };
+        consensus.begin_block(RequestBeginBlock::default()).await;
+
+        let res = consensus.deliver_tx(tx(reverter, 0, 50_000)).await;
+        assert_eq!(res.code, ExecutionError::Reverted.code());
+        assert_eq!(res.codespace, CODESPACE);
+        assert_eq!(res.gas_wanted, 50_000);
+        assert!(res.gas_used > 21_000);
+
+        // the intrinsic gas is not enough to run the code
+        let res = consensus.deliver_tx(tx(reverter, 1, 21_000)).await;
+        assert_eq!(res.code, ExecutionError::OutOfGas.code());
+
+        let res = consensus.deliver_tx(tx(logger, 2, 50_000)).await;
+        assert_eq!(res.code, 0);
+        assert!(res.codespace.is_empty());
+        assert_eq!(res.events[0].r#type, "tx");
+        let log = &res.events[1];
+        assert_eq!(log.r#type, "log");
+        let topic = log
+            .attributes
+            .iter()
+            .find(|attr| attr.key == b"topic0")
+            .unwrap();
+        assert_eq!(
+            topic.value,
+            format!("{:?}", H256::from_low_u64_be(42)).into_bytes()
+        );
+        assert!(topic.index);
+
+        // failed txs are still included and bump the sender's nonce
+        consensus.end_block(RequestEndBlock { height: 1 }).await;
+        assert_eq!(
+            consensus
+                .current_state
+                .lock()
+                .await
+                .latest_block
+                .receipts
+                .len(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn estimates_gas() {
+        let alice = Address::random();
+        let mut state = fee_free_state();
+        // `sstore(0, 1) sstore(0, 0)`, whose refund makes the gas used lower than the gas needed
+        let refunder = Address::random();
+        // `mstore(0, 42) revert(0, 32)`
+        let reverter = Address::random();
+        for (address, code) in [
+            (refunder, "6001600055600060005500"),
+            (reverter, "602a60005260206000fd"),
+        ] {
+            let code = hex::decode(code).unwrap();
+            state.insert_account_info(
+                address,
+                revm::AccountInfo {
+                    code_hash: H256(ethers::utils::keccak256(&code)),
+                    code: Some(revm::Bytecode::new_raw(code.into())),
+                    ..Default::default()
+                },
+            );
+        }
+        let info = Info {
+            state: Arc::new(Mutex::new(state.clone())),
+            versions: Default::default(),
+        };
+
+        let transfer = TransactionRequest::new().from(alice).to(Address::random());
+        match query_info(&info, Query::EstimateGas(transfer)).await {
+            QueryResponse::Gas(gas) => assert_eq!(gas, INTRINSIC_GAS.into()),
+            res => panic!("unexpected response {:?}", res),
+        }
+
+        let call = TransactionRequest::new().from(alice).to(refunder);
+        let gas = state.estimate_gas(call.clone()).await.unwrap();
+        let used = state
+            .execute(call.clone().gas(gas).into(), true)
+            .await
+            .unwrap()
+            .gas;
+        assert!(gas > used.into());
+        assert!(state.succeeds(&call, gas.as_u64()).await);
+        assert!(!state.succeeds(&call, gas.as_u64() - 1).await);
+
+        let call = TransactionRequest::new().from(alice).to(reverter);
+        let err = state.estimate_gas(call).await.unwrap_err();
+        assert_eq!(err.code(), ExecutionError::Reverted.code());
+        assert_eq!(
+            err,
+            QueryError::Execution {
+                error: ExecutionError::Reverted,
+                output: H256::from_low_u64_be(42).as_bytes().to_vec().into(),
+            }
+        );
+
+        // the provided gas limit caps the estimation
+        let call = TransactionRequest::new()
+            .from(alice)
+            .to(refunder)
+            .gas(30_000);
+        let err = state.estimate_gas(call).await.unwrap_err();
+        assert_eq!(err.code(), ExecutionError::OutOfGas.code());
+    }
+
+    #[tokio::test]
+    async fn unexecutable_txs_are_rejected() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let mut state = fee_free_state();
+        state.insert_account_info(
+            wallet.address(),
+            revm::AccountInfo {
+                balance: ethers::utils::parse_ether(1).unwrap(),
+                ..Default::default()
+            },
+        );
+        let info = Info {
+            state: Arc::new(Mutex::new(state.clone())),
+            versions: Default::default(),
+        };
+        let consensus = Consensus::new(state);
+
+        // contract creations have no recipient
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .data(vec![0x00])
+            .gas(100_000)
+            .gas_price(0)
+            .nonce(0)
+            .chain_id(1u64);
+        let res = consensus
+            .deliver_tx(RequestDeliverTx {
+                tx: sign(&wallet, tx),
+            })
+            .await;
+        assert_eq!(res.code, 0);
+
+        // queries only carry addresses, but names can still reach the execution
+        let call = TransactionRequest::new().to("vitalik.eth").gas(21000);
+        let res = info.state.lock().await.execute(call.into(), true).await;
+        assert_eq!(
+            res.unwrap_err(),
+            TxError::UnresolvedName("vitalik.eth".to_string())
+        );
+
+        // revm does not take gas limits above 64 bits
+        let call = TransactionRequest::new()
+            .to(Address::random())
+            .gas(U256::MAX);
+        let res = info
+            .query(RequestQuery {
+                data: serde_json::to_vec(&Query::EthCall(call)).unwrap(),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(
+            res.code,
+            TxError::GasLimitTooHigh {
+                maximum: u64::MAX.into(),
+                got: U256::MAX
+            }
+            .code()
+        );
+
+        let res = info
+            .query(RequestQuery {
+                data: b"not a query".to_vec(),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(res.code, QueryError::Decode(String::new()).code());
+        assert!(serde_json::from_slice::<QueryResponse>(&res.value).is_err());
+        let failure: QueryFailure = serde_json::from_slice(&res.value).unwrap();
+        assert_eq!(failure.code, res.code);
+        assert_eq!(failure.message, res.log);
+    }
+
+    #[tokio::test]
+    async fn rejects_unsigned_and_foreign_txs() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let consensus = Consensus::new(State::default());
+        let tx = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .gas(21000)
+            .nonce(0);
+
+        // the old unsigned JSON format is not accepted anymore
+        let res = consensus
+            .deliver_tx(RequestDeliverTx {
+                tx: serde_json::to_vec(&tx).unwrap(),
+            })
+            .await;
+        assert_eq!(res.code, TxError::Decode(String::new()).code());
+
+        // neither is a transaction signed for another chain
+        let res = consensus
+            .deliver_tx(RequestDeliverTx {
+                tx: sign(&wallet, tx.chain_id(1337u64)),
+            })
+            .await;
+        assert_eq!(
+            res.code,
+            TxError::WrongChainId {
+                expected: U256::one(),
+                got: Some(1337.into())
+            }
+            .code()
+        );
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn deliver_tx_rejects_arbitrary_bytes(tx in proptest::collection::vec(0u8.., 0..512)) {
+            let consensus = Consensus::new(State::default());
+            let res = block_on(consensus.deliver_tx(RequestDeliverTx { tx }));
+            proptest::prop_assert_ne!(res.code, 0);
+        }
+
+        #[test]
+        fn query_handles_arbitrary_bytes(data in proptest::collection::vec(0u8.., 0..512)) {
+            let info = Info {
+                state: Arc::new(Mutex::new(State::default())),
+                versions: Default::default(),
+            };
+            block_on(info.query(RequestQuery {
+                data,
+                ..Default::default()
+            }));
+        }
+
+        #[test]
+        fn deliver_tx_handles_arbitrary_txs(
+            to in proptest::option::of(proptest::array::uniform20(0u8..)),
+            data in proptest::collection::vec(0u8.., 0..64),
+            gas in 0u64..1_000_000,
+            gas_price in 0u64..10,
+            value in 0u64..,
+            nonce in 0u64..2,
+        ) {
+            let wallet: LocalWallet =
+                "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                    .parse()
+                    .unwrap();
+            let mut state = fee_free_state();
+            state.insert_account_info(
+                wallet.address(),
+                revm::AccountInfo {
+                    balance: ethers::utils::parse_ether(100).unwrap(),
+                    ..Default::default()
+                },
+            );
+            let consensus = Consensus::new(state);
+
+            let mut tx = TransactionRequest::new()
+                .from(wallet.address())
+                .data(data)
+                .gas(gas)
+                .gas_price(gas_price)
+                .value(value)
+                .nonce(nonce)
+                .chain_id(1u64);
+            if let Some(to) = to {
+                tx = tx.to(Address::from(to));
+            }
+            let res = block_on(async {
+                consensus.begin_block(RequestBeginBlock::default()).await;
+                let res = consensus.deliver_tx(RequestDeliverTx { tx: sign(&wallet, tx) }).await;
+                consensus.end_block(RequestEndBlock { height: 1 }).await;
+                consensus.commit(RequestCommit::default()).await;
+                res
+            });
+            // only the first nonce is valid
+            if nonce > 0 {
+                proptest::prop_assert_ne!(res.code, 0);
+            }
+        }
+
+        #[test]
+        fn query_handles_arbitrary_calls(
+            to in proptest::option::of(proptest::array::uniform20(0u8..)),
+            data in proptest::collection::vec(0u8.., 0..64),
+            gas in proptest::array::uniform4(0u64..),
+            nonce in proptest::array::uniform4(0u64..),
+        ) {
+            let info = Info {
+                state: Arc::new(Mutex::new(State::default())),
+                versions: Default::default(),
+            };
+            let mut call = TransactionRequest::new()
+                .data(data)
+                .gas(U256(gas))
+                .nonce(U256(nonce));
+            if let Some(to) = to {
+                call = call.to(Address::from(to));
+            }
+            block_on(info.query(RequestQuery {
+                data: serde_json::to_vec(&Query::EthCall(call)).unwrap(),
+                ..Default::default()
+            }));
+        }
+    }
+
+    async fn query_info(info: &Info<CacheDB<MemoryDb>>, query: Query) -> QueryResponse {
+        let res = info
+            .query(RequestQuery {
+                data: serde_json::to_vec(&query).unwrap(),
+                ..Default::default()
+            })
+            .await;
+        serde_json::from_slice(&res.value).unwrap()
     }
 }
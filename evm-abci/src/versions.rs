@@ -0,0 +1,86 @@
+use crate::State;
+use std::collections::BTreeMap;
+
+/// The number of past heights whose state is kept by default, which covers the queries racing
+/// with the latest commits.
+pub const DEFAULT_STATE_RETENTION: u64 = 8;
+
+/// The states committed at the heights before the latest one, so that queries can target a past
+/// height. Each version only keeps the root of its state trie and reads the accounts from it,
+/// so the retention window bounds the trie nodes which are kept for them: the nodes which only
+/// the pruned versions used get deleted along with them. Versions are not persisted, so they are only available
+/// for the heights committed since the app started, and historical queries fail after a restart
+/// until new heights get committed.
+#[derive(Debug)]
+pub struct StateVersions<Db> {
+    /// How many heights before the latest one are kept
+    retention: u64,
+    states: BTreeMap<i64, State<Db>>,
+}
+
+impl<Db> Default for StateVersions<Db> {
+    fn default() -> Self {
+        Self::new(DEFAULT_STATE_RETENTION)
+    }
+}
+
+impl<Db> StateVersions<Db> {
+    pub fn new(retention: u64) -> Self {
+        Self {
+            retention,
+            states: BTreeMap::new(),
+        }
+    }
+
+    /// Keeps the state which got replaced by a newer commit, and prunes the ones which fell out
+    /// of the retention window.
+    pub fn insert(&mut self, state: State<Db>) {
+        if self.retention == 0 {
+            return;
+        }
+        // the new latest height is the inserted state's one + 1
+        let earliest = state.block_height + 1 - self.retention as i64;
+        self.states.insert(state.block_height, state);
+        self.states = self.states.split_off(&earliest);
+    }
+
+    /// The state committed at `height`, if it is retained.
+    pub fn get(&self, height: i64) -> Option<&State<Db>> {
+        self.states.get(&height)
+    }
+
+    /// The lowest retained height.
+    pub fn earliest(&self) -> Option<i64> {
+        self.states.keys().next().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryDb;
+    use foundry_evm::revm::db::CacheDB;
+
+    fn state(height: i64) -> State<CacheDB<MemoryDb>> {
+        State {
+            block_height: height,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn prunes_states_out_of_the_window() {
+        let mut versions = StateVersions::new(2);
+        for height in 0..5 {
+            versions.insert(state(height));
+        }
+        // the latest height is 5, and the 2 heights before it are kept
+        assert_eq!(versions.earliest(), Some(3));
+        assert!(versions.get(2).is_none());
+        assert_eq!(versions.get(4).unwrap().block_height, 4);
+
+        let mut versions = StateVersions::new(0);
+        versions.insert(state(1));
+        assert_eq!(versions.earliest(), None);
+    }
+}
@@ -0,0 +1,154 @@
+use abci::{
+    async_api::{Consensus as _, Info as _},
+    types::*,
+};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::{get_contract_address, get_create2_address};
+use evm_abci::{
+    types::{Query, QueryResponse},
+    App, ChainSpec, GasConfig, MemoryDb,
+};
+use foundry_evm::revm::db::CacheDB;
+
+/// Code of a contract which always returns 42.
+const RUNTIME_CODE: &str = "602a60005260206000f3";
+
+/// Init code which deploys [`RUNTIME_CODE`].
+const INIT_CODE: &str = "600a600c600039600a6000f3602a60005260206000f3";
+
+/// Init code of a factory which deploys [`INIT_CODE`] with CREATE2 and a zero salt when called,
+/// and returns the deployed address.
+const FACTORY_INIT_CODE: &str = "602e600c600039602e6000f3601660186000396000601660006000f560005260206000f3600a600c600039600a6000f3602a60005260206000f3";
+
+/// The demo account funded by `App::new`.
+fn alice() -> LocalWallet {
+    "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        .parse()
+        .unwrap()
+}
+
+fn app() -> App<CacheDB<MemoryDb>> {
+    let gas_config = GasConfig {
+        initial_base_fee: U256::zero(),
+        ..Default::default()
+    };
+    App::new(true, gas_config, ChainSpec::default(), 0)
+}
+
+/// Runs a block with the provided transactions and returns their results.
+async fn run_block(
+    app: &App<CacheDB<MemoryDb>>,
+    height: i64,
+    txs: Vec<TypedTransaction>,
+) -> Vec<ResponseDeliverTx> {
+    let wallet = alice();
+    app.consensus
+        .begin_block(RequestBeginBlock::default())
+        .await;
+    let mut results = Vec::new();
+    for tx in txs {
+        let sig = wallet.sign_transaction_sync(&tx);
+        let tx = tx.rlp_signed(&sig).to_vec();
+        results.push(app.consensus.deliver_tx(RequestDeliverTx { tx }).await);
+    }
+    app.consensus.end_block(RequestEndBlock { height }).await;
+    app.consensus.commit(RequestCommit::default()).await;
+    results
+}
+
+async fn query(app: &App<CacheDB<MemoryDb>>, query: Query) -> QueryResponse {
+    let res = app
+        .info
+        .query(RequestQuery {
+            data: serde_json::to_vec(&query).unwrap(),
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(res.code, 0, "{}", res.log);
+    serde_json::from_slice(&res.value).unwrap()
+}
+
+/// Calls the contract at `to` and returns its output.
+async fn call(app: &App<CacheDB<MemoryDb>>, to: Address) -> Bytes {
+    let call = TransactionRequest::new()
+        .from(alice().address())
+        .to(to)
+        .gas(100_000);
+    match query(app, Query::EthCall(call)).await {
+        QueryResponse::Tx(res) => match res.out {
+            foundry_evm::revm::TransactOut::Call(out) => out.into(),
+            out => panic!("unexpected output {:?}", out),
+        },
+        res => panic!("unexpected response {:?}", res),
+    }
+}
+
+fn deployment(code: &str, nonce: u64) -> TypedTransaction {
+    TransactionRequest::new()
+        .from(alice().address())
+        .data(hex::decode(code).unwrap())
+        .gas(200_000)
+        .gas_price(0)
+        .nonce(nonce)
+        .chain_id(1u64)
+        .into()
+}
+
+#[tokio::test]
+async fn deploys_and_calls_contracts() {
+    let app = app();
+    let alice = alice().address();
+
+    let results = run_block(&app, 1, vec![deployment(INIT_CODE, 0)]).await;
+    assert_eq!(results[0].code, 0, "{}", results[0].log);
+
+    // the receipt has the deployed address
+    let address = get_contract_address(alice, 0u64);
+    let hash = match query(&app, Query::BlockByNumber(1)).await {
+        QueryResponse::Block(Some(block)) => block.transactions[0],
+        res => panic!("unexpected response {:?}", res),
+    };
+    let receipt = match query(&app, Query::Receipt(hash)).await {
+        QueryResponse::Receipt(Some(receipt)) => receipt,
+        res => panic!("unexpected response {:?}", res),
+    };
+    assert_eq!(receipt.contract_address, Some(address));
+    assert_eq!(receipt.status, 1.into());
+
+    let code = match query(&app, Query::Code(address)).await {
+        QueryResponse::Code(code) => code,
+        res => panic!("unexpected response {:?}", res),
+    };
+    assert_eq!(code.to_vec(), hex::decode(RUNTIME_CODE).unwrap());
+    assert_eq!(
+        call(&app, address).await.as_ref(),
+        H256::from_low_u64_be(42).as_bytes()
+    );
+}
+
+#[tokio::test]
+async fn deploys_contracts_from_factories() {
+    let app = app();
+    let alice = alice().address();
+
+    let results = run_block(&app, 1, vec![deployment(FACTORY_INIT_CODE, 0)]).await;
+    assert_eq!(results[0].code, 0, "{}", results[0].log);
+    let factory = get_contract_address(alice, 0u64);
+
+    let create = TransactionRequest::new()
+        .from(alice)
+        .to(factory)
+        .gas(200_000)
+        .gas_price(0)
+        .nonce(1)
+        .chain_id(1u64);
+    let results = run_block(&app, 2, vec![create.into()]).await;
+    assert_eq!(results[0].code, 0, "{}", results[0].log);
+
+    let child = get_create2_address(factory, vec![0u8; 32], hex::decode(INIT_CODE).unwrap());
+    assert_eq!(
+        call(&app, child).await.as_ref(),
+        H256::from_low_u64_be(42).as_bytes()
+    );
+}
@@ -0,0 +1,78 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use narwhal_abci::WorkerStores;
+use narwhal_crypto::Digest;
+use rocksdb::{Options, DB};
+
+/// The number of batches read per iteration, e.g. the payload of a few certificates.
+const BATCHES: u8 = 32;
+/// The size of each batch, e.g. 500 transactions of 100 bytes.
+const BATCH_SIZE: usize = 50_000;
+
+/// Fills the database of worker 0 of the primary whose store is at `store_path`. The returned
+/// handle stands for the running worker, which keeps its database open.
+fn worker(store_path: &str) -> (DB, Vec<Digest>) {
+    let db = DB::open_default(format!("{}-0", store_path)).unwrap();
+    let digests = (0..BATCHES)
+        .map(|i| {
+            let digest = Digest([i; 32]);
+            db.put(digest.to_vec(), vec![i; BATCH_SIZE]).unwrap();
+            digest
+        })
+        .collect();
+    (db, digests)
+}
+
+fn read_batches(c: &mut Criterion) {
+    let store_path = std::env::temp_dir()
+        .join(format!("narwhal-abci-bench-{}", std::process::id()))
+        .to_string_lossy()
+        .into_owned();
+    let (worker_db, digests) = worker(&store_path);
+    let mut stores = WorkerStores::new(&store_path);
+    let worker_path = stores.worker_path(0);
+
+    let mut group = c.benchmark_group("read_batches");
+    // what the Engine used to do for every batch
+    group.bench_function("open_per_batch", |b| {
+        b.iter(|| {
+            for digest in &digests {
+                let db = DB::open_for_read_only(&Options::default(), &worker_path, true).unwrap();
+                db.get(digest.to_vec()).unwrap().unwrap();
+            }
+        })
+    });
+    group.bench_function("cached_handle", |b| {
+        b.iter(|| {
+            for digest in &digests {
+                stores.read(0, digest).unwrap().unwrap();
+            }
+        })
+    });
+    // a batch written after the handle was opened is only found after catching up
+    group.bench_function("cached_handle_catch_up", |b| {
+        let mut i = 0u64;
+        b.iter_batched(
+            || {
+                i += 1;
+                let mut key = [0xff; 32];
+                key[..8].copy_from_slice(&i.to_be_bytes());
+                let digest = Digest(key);
+                worker_db.put(digest.to_vec(), vec![0; BATCH_SIZE]).unwrap();
+                digest
+            },
+            |digest| stores.read(0, &digest).unwrap().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+
+    for path in [
+        format!("{}-0", store_path),
+        format!("{}-0-abci-secondary", store_path),
+    ] {
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
+
+criterion_group!(benches, read_batches);
+criterion_main!(benches);
@@ -0,0 +1,164 @@
+use bytes::{BufMut, BytesMut};
+use futures::{SinkExt, StreamExt};
+use prost::Message;
+use std::net::SocketAddr;
+use tendermint_proto::abci::{
+    request, response, Request, RequestBeginBlock, RequestCheckTx, RequestCommit, RequestDeliverTx,
+    RequestEndBlock, RequestInfo, RequestInitChain, RequestQuery, Response, ResponseBeginBlock,
+    ResponseCheckTx, ResponseCommit, ResponseDeliverTx, ResponseEndBlock, ResponseInfo,
+    ResponseInitChain, ResponseQuery,
+};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+/// The maximum length of a varint, in bytes.
+const MAX_VARINT_LENGTH: usize = 10;
+
+/// An ABCI socket client which does not block the runtime while the app handles a request,
+/// speaking the same protocol as Tendermint's. Each client is one connection to the app, which
+/// answers its requests in order.
+pub struct AbciClient {
+    stream: Framed<TcpStream, AbciCodec>,
+}
+
+impl AbciClient {
+    pub async fn connect(address: SocketAddr) -> eyre::Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Self {
+            stream: Framed::new(stream, AbciCodec),
+        })
+    }
+
+    /// Sends the request and waits for the app's response.
+    async fn perform(&mut self, request: request::Value) -> eyre::Result<response::Value> {
+        self.stream
+            .send(Request {
+                value: Some(request),
+            })
+            .await?;
+        let response = self
+            .stream
+            .next()
+            .await
+            .ok_or_else(|| eyre::eyre!("the app closed the connection"))??;
+        match response.value {
+            Some(response::Value::Exception(err)) => eyre::bail!(err.error),
+            Some(value) => Ok(value),
+            None => eyre::bail!("empty response"),
+        }
+    }
+
+    pub async fn info(&mut self, req: RequestInfo) -> eyre::Result<ResponseInfo> {
+        match self.perform(request::Value::Info(req)).await? {
+            response::Value::Info(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+
+    pub async fn init_chain(&mut self, req: RequestInitChain) -> eyre::Result<ResponseInitChain> {
+        match self.perform(request::Value::InitChain(req)).await? {
+            response::Value::InitChain(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+
+    pub async fn query(&mut self, req: RequestQuery) -> eyre::Result<ResponseQuery> {
+        match self.perform(request::Value::Query(req)).await? {
+            response::Value::Query(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+
+    pub async fn check_tx(&mut self, req: RequestCheckTx) -> eyre::Result<ResponseCheckTx> {
+        match self.perform(request::Value::CheckTx(req)).await? {
+            response::Value::CheckTx(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+
+    pub async fn begin_block(
+        &mut self,
+        req: RequestBeginBlock,
+    ) -> eyre::Result<ResponseBeginBlock> {
+        match self.perform(request::Value::BeginBlock(req)).await? {
+            response::Value::BeginBlock(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+
+    pub async fn deliver_tx(&mut self, req: RequestDeliverTx) -> eyre::Result<ResponseDeliverTx> {
+        match self.perform(request::Value::DeliverTx(req)).await? {
+            response::Value::DeliverTx(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+
+    pub async fn end_block(&mut self, req: RequestEndBlock) -> eyre::Result<ResponseEndBlock> {
+        match self.perform(request::Value::EndBlock(req)).await? {
+            response::Value::EndBlock(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+
+    pub async fn commit(&mut self) -> eyre::Result<ResponseCommit> {
+        match self
+            .perform(request::Value::Commit(RequestCommit {}))
+            .await?
+        {
+            response::Value::Commit(res) => Ok(res),
+            res => eyre::bail!("unexpected response {:?}", res),
+        }
+    }
+}
+
+/// Frames the protobuf messages with their length, as a signed (zigzag) varint like Tendermint.
+struct AbciCodec;
+
+impl Encoder<Request> for AbciCodec {
+    type Error = eyre::Report;
+
+    fn encode(&mut self, request: Request, dst: &mut BytesMut) -> eyre::Result<()> {
+        let mut length = (request.encoded_len() as u64) << 1;
+        while length >= 0x80 {
+            dst.put_u8(length as u8 | 0x80);
+            length >>= 7;
+        }
+        dst.put_u8(length as u8);
+        request.encode(dst)?;
+        Ok(())
+    }
+}
+
+impl Decoder for AbciCodec {
+    type Item = Response;
+    type Error = eyre::Report;
+
+    fn decode(&mut self, src: &mut BytesMut) -> eyre::Result<Option<Response>> {
+        let (prefix, length) = match decode_length(src)? {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+        if src.len() < prefix + length {
+            src.reserve(prefix + length - src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(prefix + length);
+        Ok(Some(Response::decode(&frame[prefix..])?))
+    }
+}
+
+/// Reads the length prefix of a frame, returning the length of the prefix and the one of the
+/// message, or `None` if the prefix was not fully received yet.
+fn decode_length(src: &[u8]) -> eyre::Result<Option<(usize, usize)>> {
+    let mut value = 0u64;
+    for (i, byte) in src.iter().take(MAX_VARINT_LENGTH).enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((i + 1, (value >> 1) as usize)));
+        }
+    }
+    if src.len() >= MAX_VARINT_LENGTH {
+        eyre::bail!("invalid message length prefix");
+    }
+    Ok(None)
+}
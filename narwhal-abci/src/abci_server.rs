@@ -1,8 +1,9 @@
-use crate::{AbciQueryQuery, BroadcastTxQuery};
+use crate::{AbciQueryQuery, BroadcastTxQuery, BroadcastTxResponse, EngineEvent};
 
 use eyre::WrapErr;
 use futures::SinkExt;
-use tendermint_proto::abci::ResponseQuery;
+use tendermint_proto::abci::{ResponseCheckTx, ResponseQuery};
+use tokio::sync::broadcast::{error::RecvError, Sender as BroadcastSender};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot::{channel as oneshot_channel, Sender as OneShotSender};
 
@@ -13,22 +14,32 @@ use warp::{Filter, Rejection};
 use std::net::SocketAddr;
 
 /// Simple HTTP API server which listens to messages on:
-/// * `broadcast_tx`: forwards them to Narwhal's mempool/worker socket, which will proceed to put
-/// it in the consensus process and eventually forward it to the application.
+/// * `broadcast_tx`: checks them with the application's CheckTx and forwards them to Narwhal's
+/// mempool/worker socket, which will proceed to put it in the consensus process and eventually
+/// forward it to the application. Transactions prefixed with `0x` are hex-decoded before being
+/// forwarded.
 /// * `abci_query`: forwards them over a channel to a handler (typically the application).
+/// * `events`: streams the Engine's events as server-sent events, e.g. the results of each
+/// committed block.
 pub struct AbciApi<T> {
     mempool_address: SocketAddr,
     tx: Sender<(OneShotSender<T>, AbciQueryQuery)>,
+    check_tx: Sender<(OneShotSender<ResponseCheckTx>, Vec<u8>)>,
+    events: BroadcastSender<EngineEvent>,
 }
 
 impl<T: Send + Sync + std::fmt::Debug> AbciApi<T> {
     pub fn new(
         mempool_address: SocketAddr,
         tx: Sender<(OneShotSender<T>, AbciQueryQuery)>,
+        check_tx: Sender<(OneShotSender<ResponseCheckTx>, Vec<u8>)>,
+        events: BroadcastSender<EngineEvent>,
     ) -> Self {
         Self {
             mempool_address,
             tx,
+            check_tx,
+            events,
         }
     }
 }
@@ -37,22 +48,70 @@ impl AbciApi<ResponseQuery> {
     pub fn routes(self) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         let route_broadcast_tx = warp::path("broadcast_tx")
             .and(warp::query::<BroadcastTxQuery>())
-            .and_then(move |req: BroadcastTxQuery| async move {
-                log::warn!("broadcast_tx: {:?}", req);
+            .and_then(move |req: BroadcastTxQuery| {
+                let tx_check_txs = self.check_tx.clone();
+                async move {
+                    log::warn!("broadcast_tx: {:?}", req);
+
+                    // `0x`-prefixed transactions are hex-encoded binary payloads (e.g. RLP)
+                    let tx = match req.tx.strip_prefix("0x") {
+                        Some(hex_tx) => match hex::decode(hex_tx) {
+                            Ok(tx) => tx,
+                            Err(e) => {
+                                return Ok::<_, Rejection>(warp::reply::json(
+                                    &BroadcastTxResponse::error(e),
+                                ))
+                            }
+                        },
+                        None => req.tx.clone().into_bytes(),
+                    };
+
+                    // Validate the tx with the app before putting it in a batch
+                    let (tx_resp, rx_resp) = oneshot_channel();
+                    if let Err(err) = tx_check_txs.send((tx_resp, tx.clone())).await {
+                        log::error!("Error forwarding check tx: {}", err);
+                    };
+                    let resp = match rx_resp.await {
+                        Ok(resp) => resp,
+                        Err(e) => {
+                            return Ok::<_, Rejection>(warp::reply::json(
+                                &BroadcastTxResponse::error(e),
+                            ))
+                        }
+                    };
+                    if resp.code != 0 {
+                        log::warn!("broadcast_tx: {:?} rejected: {}", req, resp.log);
+                        return Ok::<_, Rejection>(warp::reply::json(&BroadcastTxResponse {
+                            code: resp.code,
+                            log: resp.log,
+                            codespace: resp.codespace,
+                        }));
+                    }
 
-                let stream = TcpStream::connect(self.mempool_address)
-                    .await
-                    .wrap_err(format!(
-                        "ROUTE_BROADCAST_TX failed to connect to {}",
-                        self.mempool_address
-                    ))
-                    .unwrap();
-                let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+                    let connection =
+                        TcpStream::connect(self.mempool_address)
+                            .await
+                            .wrap_err(format!(
+                                "ROUTE_BROADCAST_TX failed to connect to {}",
+                                self.mempool_address
+                            ));
+                    let stream = match connection {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log::error!("{:#}", e);
+                            // the alternate format keeps the cause, e.g. a refused connection
+                            return Ok::<_, Rejection>(warp::reply::json(
+                                &BroadcastTxResponse::error(format!("{:#}", e)),
+                            ));
+                        }
+                    };
+                    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
 
-                if let Err(e) = transport.send(req.tx.clone().into()).await {
-                    Ok::<_, Rejection>(format!("ERROR IN: broadcast_tx: {:?}. Err: {}", req, e))
-                } else {
-                    Ok::<_, Rejection>(format!("broadcast_tx: {:?}", req))
+                    let resp = match transport.send(tx.into()).await {
+                        Ok(_) => BroadcastTxResponse::default(),
+                        Err(e) => BroadcastTxResponse::error(e),
+                    };
+                    Ok::<_, Rejection>(warp::reply::json(&resp))
                 }
             });
 
@@ -68,12 +127,39 @@ impl AbciApi<ResponseQuery> {
                         Ok(_) => {}
                         Err(err) => log::error!("Error forwarding abci query: {}", err),
                     };
-                    let resp = rx.await.unwrap();
+                    let value = match rx.await {
+                        Ok(resp) => resp.value,
+                        Err(err) => {
+                            log::error!("abci query dropped: {}", err);
+                            format!("could not forward the query: {}", err).into_bytes()
+                        }
+                    };
                     // Return the value
-                    Ok::<_, Rejection>(resp.value)
+                    Ok::<_, Rejection>(value)
+                }
+            });
+
+        let route_events = warp::path("events").map(move || {
+            // each subscriber gets the events published after it connected
+            let events = futures::stream::unfold(self.events.subscribe(), |mut rx| async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(event) => {
+                            let event = warp::sse::Event::default()
+                                .event(event.name())
+                                .json_data(&event);
+                            return Some((event, rx));
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            log::warn!("events subscriber lagged, skipped {} events", skipped)
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
                 }
             });
+            warp::sse::reply(warp::sse::keep_alive().stream(events))
+        });
 
-        route_broadcast_tx.or(route_abci_query)
+        route_broadcast_tx.or(route_abci_query).or(route_events)
     }
 }
@@ -0,0 +1,133 @@
+use crate::LoadedCertificate;
+use narwhal_crypto::PublicKey;
+use std::str::FromStr;
+
+/// Where the Engine ends a block in the sequence of certificates output by consensus. Every node
+/// must use the same policy, since the blocks they execute must be the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockBoundary {
+    /// Each certificate is a block of its own.
+    Certificate,
+    /// A block ends at each certificate of an elected leader, which approximates the sub-DAGs
+    /// committed by consensus: a committed leader is the last certificate of its sub-DAG, but a
+    /// skipped leader which is output within a later leader's sub-DAG ends a block too. The
+    /// consensus output does not tell the sub-DAGs apart, and the blocks only depend on the
+    /// sequence of certificates either way, so all the nodes still agree on them.
+    SubDag,
+    /// A block holds the certificates of a round, and ends at the leaders like
+    /// [`BlockBoundary::SubDag`] does.
+    Round,
+}
+
+impl FromStr for BlockBoundary {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        match s {
+            "certificate" => Ok(BlockBoundary::Certificate),
+            "sub-dag" => Ok(BlockBoundary::SubDag),
+            "round" => Ok(BlockBoundary::Round),
+            _ => eyre::bail!("unknown block boundary {}", s),
+        }
+    }
+}
+
+/// How the Engine groups the certificates into blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockPolicy {
+    pub boundary: BlockBoundary,
+    /// Ends a block early once it holds at least this many transactions. Certificates are never
+    /// split, so a block can go over it.
+    pub max_txs: Option<usize>,
+    /// Whether to execute the blocks without transactions, e.g. of payload-less certificates
+    pub skip_empty: bool,
+}
+
+impl Default for BlockPolicy {
+    fn default() -> Self {
+        Self {
+            boundary: BlockBoundary::Certificate,
+            max_txs: None,
+            skip_empty: false,
+        }
+    }
+}
+
+/// Groups the certificates output by consensus into blocks, following a [`BlockPolicy`]. The
+/// blocks only depend on the sequence of certificates, which is the same on all the nodes, and
+/// not on when they are output.
+pub struct BlockBuilder {
+    policy: BlockPolicy,
+    /// The committee's public keys, sorted, to elect the leaders like consensus does
+    authorities: Vec<PublicKey>,
+    /// The certificates of the block being built
+    pending: Vec<LoadedCertificate>,
+    /// The number of transactions of the block being built
+    txs: usize,
+}
+
+impl BlockBuilder {
+    pub fn new(policy: BlockPolicy, mut authorities: Vec<PublicKey>) -> Self {
+        authorities.sort();
+        Self {
+            policy,
+            authorities,
+            pending: Vec::new(),
+            txs: 0,
+        }
+    }
+
+    pub fn policy(&self) -> &BlockPolicy {
+        &self.policy
+    }
+
+    /// The certificates of the block being built.
+    pub fn pending(&self) -> &[LoadedCertificate] {
+        &self.pending
+    }
+
+    /// Adds the next certificate output by consensus, and returns the blocks it completes, in
+    /// order, each as the certificates it holds.
+    pub fn push(&mut self, loaded: LoadedCertificate) -> Vec<Vec<LoadedCertificate>> {
+        let mut blocks = Vec::new();
+        let round = loaded.certificate.header.round;
+        // a new round starts a new block
+        if self.policy.boundary == BlockBoundary::Round
+            && matches!(self.pending.last(), Some(last) if last.certificate.header.round != round)
+        {
+            blocks.push(self.take());
+        }
+
+        let leader = self.is_leader(&loaded);
+        self.txs += loaded.txs();
+        self.pending.push(loaded);
+        let end = match self.policy.boundary {
+            BlockBoundary::Certificate => true,
+            // a committed leader is the last certificate of its sub-DAG, but a skipped one may
+            // be output in the middle of a later leader's sub-DAG, and end the block there
+            BlockBoundary::SubDag | BlockBoundary::Round => leader,
+        };
+        let full = matches!(self.policy.max_txs, Some(max_txs) if self.txs >= max_txs);
+        if end || full {
+            blocks.push(self.take());
+        }
+        blocks
+    }
+
+    fn take(&mut self) -> Vec<LoadedCertificate> {
+        self.txs = 0;
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Whether the certificate is the one of its round's leader, which consensus elects in
+    /// the even rounds, round-robin over the sorted committee. It is not told whether consensus
+    /// committed the leader or skipped it.
+    fn is_leader(&self, loaded: &LoadedCertificate) -> bool {
+        let header = &loaded.certificate.header;
+        if header.round % 2 != 0 || self.authorities.is_empty() {
+            return false;
+        }
+        let leader = &self.authorities[header.round as usize % self.authorities.len()];
+        &header.author == leader
+    }
+}
@@ -0,0 +1,183 @@
+use crate::BlockResults;
+use eyre::WrapErr;
+use narwhal_crypto::Digest;
+use rocksdb::{IteratorMode, WriteBatch, DB};
+use std::convert::TryInto;
+use std::sync::Arc;
+
+const HEIGHT_PREFIX: u8 = b'h';
+const DIGEST_PREFIX: u8 = b'd';
+const RESULTS_PREFIX: u8 = b'r';
+const PENDING_KEY: &[u8] = b"p";
+
+/// The length of a certificate digest, in bytes.
+const DIGEST_LENGTH: usize = 32;
+
+/// Write-ahead log of the certificates executed by the Engine, persisted in its own RocksDB
+/// database. It maps each block height to the digests of the certificates it was built from, and
+/// back, so that the Engine can replay the certificates which the app did not commit and skip the
+/// ones which were already executed. It also keeps the certificates of the blocks being built,
+/// and what the app returned for each block. Clones share the same database.
+#[derive(Clone)]
+pub struct CertificateLog {
+    db: Arc<DB>,
+}
+
+impl CertificateLog {
+    pub fn open(path: &str) -> eyre::Result<Self> {
+        let db = DB::open_default(path)
+            .wrap_err(format!("could not open certificate log at {}", path))?;
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    /// Records that the certificates with the provided digests are executed at `height`, in
+    /// order.
+    pub fn append(&self, height: i64, digests: &[Digest]) -> eyre::Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put(
+            height_key(height),
+            digests.iter().flat_map(Digest::to_vec).collect::<Vec<_>>(),
+        );
+        for digest in digests {
+            batch.put(digest_key(digest), height.to_be_bytes());
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// The digests of the certificates executed at `height`, in order.
+    pub fn digests(&self, height: i64) -> eyre::Result<Option<Vec<Digest>>> {
+        self.db
+            .get(height_key(height))?
+            .map(|digests| {
+                decode_digests(&digests).wrap_err(format!("corrupted digests at height {}", height))
+            })
+            .transpose()
+    }
+
+    /// Records the certificates which were output by consensus but not executed yet, in order,
+    /// replacing the previous ones. They must be logged before they are executed, so that a
+    /// restart can rebuild the blocks they are part of.
+    pub fn set_pending(&self, digests: &[Digest]) -> eyre::Result<()> {
+        self.db.put(
+            PENDING_KEY,
+            digests.iter().flat_map(Digest::to_vec).collect::<Vec<_>>(),
+        )?;
+        Ok(())
+    }
+
+    /// The pending certificates which were not executed since they were logged, in order.
+    pub fn pending(&self) -> eyre::Result<Vec<Digest>> {
+        let digests = match self.db.get(PENDING_KEY)? {
+            Some(digests) => decode_digests(&digests).wrap_err("corrupted pending digests")?,
+            None => return Ok(Vec::new()),
+        };
+        let mut pending = Vec::new();
+        for digest in digests {
+            if self.height(&digest)?.is_none() {
+                pending.push(digest);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// The height at which the certificate with the provided digest was executed, if any.
+    pub fn height(&self, digest: &Digest) -> eyre::Result<Option<i64>> {
+        self.db
+            .get(digest_key(digest))?
+            .map(|height| decode_height(&height))
+            .transpose()
+    }
+
+    /// Records what the app returned while executing the block at `results.height`.
+    pub fn put_results(&self, results: &BlockResults) -> eyre::Result<()> {
+        self.db
+            .put(results_key(results.height), serde_json::to_vec(results)?)?;
+        Ok(())
+    }
+
+    /// What the app returned while executing the block at `height`, if it was committed.
+    pub fn results(&self, height: i64) -> eyre::Result<Option<BlockResults>> {
+        self.db
+            .get(results_key(height))?
+            .map(|results| {
+                serde_json::from_slice(&results)
+                    .wrap_err(format!("corrupted results at height {}", height))
+            })
+            .transpose()
+    }
+
+    /// The highest logged height, or 0 if the log is empty.
+    pub fn last_height(&self) -> eyre::Result<i64> {
+        // heights are big endian encoded, so the last `h` key is the highest height
+        let mut end = vec![HEIGHT_PREFIX];
+        end.extend_from_slice(&[0xff; 9]);
+        let mode = IteratorMode::From(&end, rocksdb::Direction::Reverse);
+        match self.db.iterator(mode).next() {
+            Some((key, _)) if key.first() == Some(&HEIGHT_PREFIX) => decode_height(&key[1..]),
+            _ => Ok(0),
+        }
+    }
+}
+
+fn height_key(height: i64) -> Vec<u8> {
+    let mut key = vec![HEIGHT_PREFIX];
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+fn results_key(height: i64) -> Vec<u8> {
+    let mut key = vec![RESULTS_PREFIX];
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+fn digest_key(digest: &Digest) -> Vec<u8> {
+    let mut key = vec![DIGEST_PREFIX];
+    key.extend_from_slice(&digest.to_vec());
+    key
+}
+
+fn decode_digests(bytes: &[u8]) -> eyre::Result<Vec<Digest>> {
+    if bytes.len() % DIGEST_LENGTH != 0 {
+        eyre::bail!("invalid digests length {}", bytes.len());
+    }
+    Ok(bytes
+        .chunks(DIGEST_LENGTH)
+        .map(|digest| {
+            digest
+                .try_into()
+                .expect("the chunks have the digest length")
+        })
+        .collect())
+}
+
+fn decode_height(bytes: &[u8]) -> eyre::Result<i64> {
+    let bytes = bytes.try_into().wrap_err("corrupted height")?;
+    Ok(i64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_certificates_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("narwhal-abci-log-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(path);
+        let digests: Vec<_> = (1..=3).map(|i| Digest([i; DIGEST_LENGTH])).collect();
+
+        let log = CertificateLog::open(path).unwrap();
+        log.set_pending(&digests).unwrap();
+        // the first certificate makes a block of its own, then the node stops
+        log.append(1, &digests[..1]).unwrap();
+        drop(log);
+
+        let log = CertificateLog::open(path).unwrap();
+        assert_eq!(log.pending().unwrap(), digests[1..]);
+        assert_eq!(log.height(&digests[0]).unwrap(), Some(1));
+        drop(log);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -1,206 +1,341 @@
-use crate::AbciQueryQuery;
+use crate::{
+    AbciClient, BatchLoader, BatchSync, BlockBuilder, BlockResults, CertificateLog, EngineEvent,
+    LoadedCertificate,
+};
+use eyre::WrapErr;
 use std::net::SocketAddr;
-use tokio::sync::mpsc::Receiver;
-use tokio::sync::oneshot::Sender as OneShotSender;
+use tokio::sync::broadcast::Sender as BroadcastSender;
+use tokio::sync::mpsc::{channel, Receiver};
 
 // Tendermint Types
-use tendermint_abci::{Client as AbciClient, ClientBuilder};
 use tendermint_proto::abci::{
-    RequestBeginBlock, RequestDeliverTx, RequestEndBlock, RequestInfo, RequestInitChain,
-    RequestQuery, ResponseQuery,
+    LastCommitInfo, RequestBeginBlock, RequestDeliverTx, RequestEndBlock, RequestInfo,
+    RequestInitChain, ResponseCommit, ResponseDeliverTx, ResponseEndBlock,
 };
+use tendermint_proto::google::protobuf::Timestamp;
 use tendermint_proto::types::Header;
 
 // Narwhal types
-use narwhal_crypto::Digest;
+use narwhal_crypto::{Digest, Hash};
 use narwhal_primary::Certificate;
 
-/// The engine drives the ABCI Application by concurrently polling for:
-/// 1. Calling the BeginBlock -> DeliverTx -> EndBlock -> Commit event loop on the ABCI App on each Bullshark
-///    certificate received. It will also call Info and InitChain to initialize the ABCI App if
-///    necessary.
-/// 2. Processing Query & Broadcast Tx messages received from the Primary's ABCI Server API and forwarding them to the
-///    ABCI App via a Tendermint protobuf client.
+/// The engine drives the ABCI Application by calling the BeginBlock -> DeliverTx -> EndBlock ->
+/// Commit event loop on the ABCI App on each block formed from the Bullshark certificates
+/// received, as configured by the [`BlockPolicy`](crate::BlockPolicy). It will also call
+/// Info and InitChain to initialize the ABCI App if necessary. The Query & Broadcast Tx messages
+/// received from the Primary's ABCI Server API are forwarded to the app over their own
+/// connections, by [`serve_queries`](crate::serve_queries) and
+/// [`serve_check_txs`](crate::serve_check_txs), so they do not wait for the blocks.
+///
+/// The batches of the upcoming certificates are loaded by a [`BatchLoader`] task while the
+/// current one is delivered. Batches which the workers have not stored yet are requested from
+/// the certificate's author and waited for, as configured by [`BatchSync`].
+///
+/// The app's responses to each block are persisted in the certificate log and published as
+/// [`EngineEvent`]s.
 pub struct Engine {
     /// The address of the ABCI app
     pub app_address: SocketAddr,
     /// The path to the Primary's store, so that the Engine can query each of the Primary's workers
     /// for the data corresponding to a Certificate
     pub store_path: String,
-    /// Messages received from the ABCI Server to be forwarded to the engine.
-    pub rx_abci_queries: Receiver<(OneShotSender<ResponseQuery>, AbciQueryQuery)>,
     /// The last block height, initialized to the application's latest block by default
     pub last_block_height: i64,
+    /// The app-specific genesis state, sent to the app in `InitChain`
+    pub genesis: Vec<u8>,
+    /// The UNIX time of round 0, from which the blocks' timestamps are derived
+    pub genesis_time: u64,
+    /// The timestamp of the last block, so that timestamps never go back
+    pub last_timestamp: u64,
+    /// The height -> certificate digest log, used to replay certificates after a restart
+    pub log: CertificateLog,
+    /// Publishes the results of each committed block, e.g. to the ABCI Server API. Sending
+    /// only fails when nobody is subscribed, which is fine.
+    pub tx_events: BroadcastSender<EngineEvent>,
+    /// How to get the batches missing from the workers' stores
+    pub batch_sync: BatchSync,
+    /// How many loaded certificates can wait for their execution, which bounds how far ahead
+    /// of the execution the batches are loaded
+    pub prefetch: usize,
+    /// Groups the certificates into blocks
+    pub blocks: BlockBuilder,
+    /// The connection to the app on which blocks are executed
     pub client: AbciClient,
-    pub req_client: AbciClient,
 }
 
 impl Engine {
-    pub fn new(
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
         app_address: SocketAddr,
         store_path: &str,
-        rx_abci_queries: Receiver<(OneShotSender<ResponseQuery>, AbciQueryQuery)>,
-    ) -> Self {
-        let mut client = ClientBuilder::default().connect(&app_address).unwrap();
+        genesis: Vec<u8>,
+        genesis_time: u64,
+        tx_events: BroadcastSender<EngineEvent>,
+        batch_sync: BatchSync,
+        prefetch: usize,
+        blocks: BlockBuilder,
+    ) -> eyre::Result<Self> {
+        let mut client = AbciClient::connect(app_address)
+            .await
+            .wrap_err(format!("could not connect to the app at {}", app_address))?;
 
         let last_block_height = client
             .info(RequestInfo::default())
+            .await
             .map(|res| res.last_block_height)
             .unwrap_or_default();
 
-        // Instantiate a new client to not be locked in an Info connection
-        let client = ClientBuilder::default().connect(&app_address).unwrap();
-        let req_client = ClientBuilder::default().connect(&app_address).unwrap();
-        Self {
+        let log = CertificateLog::open(&format!("{}-abci", store_path))?;
+        Ok(Self {
             app_address,
             store_path: store_path.to_string(),
-            rx_abci_queries,
             last_block_height,
+            genesis,
+            genesis_time,
+            last_timestamp: genesis_time,
+            log,
+            tx_events,
+            batch_sync,
+            prefetch,
+            blocks,
             client,
-            req_client,
-        }
+        })
     }
 
     /// Receives an ordered list of certificates and apply any application-specific logic.
-    pub async fn run(&mut self, mut rx_output: Receiver<Certificate>) -> eyre::Result<()> {
-        self.init_chain()?;
-
-        loop {
-            tokio::select! {
-                Some(certificate) = rx_output.recv() => {
-                    self.handle_cert(certificate)?;
-                },
-                Some((tx, req)) = self.rx_abci_queries.recv() => {
-                    self.handle_abci_query(tx, req)?;
-                }
-                else => break,
+    pub async fn run(&mut self, rx_output: Receiver<Certificate>) -> eyre::Result<()> {
+        self.init_chain().await?;
+        let mut loader = BatchLoader::new(&self.store_path, self.batch_sync.clone());
+        self.replay(&mut loader).await?;
+        self.restore_pending(&mut loader).await?;
+
+        // the channel needs some capacity, and then the loader is one certificate ahead
+        let (tx_loaded, mut rx_loaded) = channel(self.prefetch.max(1));
+        loader.spawn(self.log.clone(), rx_output, tx_loaded);
+
+        while let Some(loaded) = rx_loaded.recv().await {
+            let blocks = self.blocks.push(loaded?);
+            // the certificates are logged before their blocks are executed, so that a restart
+            // does not lose the ones which consensus will not output again
+            let digests: Vec<_> = blocks
+                .iter()
+                .flatten()
+                .chain(self.blocks.pending())
+                .map(|loaded| loaded.certificate.digest())
+                .collect();
+            self.log.set_pending(&digests)?;
+            for block in blocks {
+                self.handle_block(block).await?;
             }
         }
 
         Ok(())
     }
 
-    /// On each new certificate, increment the block height to proposed and run through the
-    /// BeginBlock -> DeliverTx for each tx in the certificate -> EndBlock -> Commit event loop.
-    fn handle_cert(&mut self, certificate: Certificate) -> eyre::Result<()> {
+    /// On each new block, increment the block height to proposed, log it and run through
+    /// the BeginBlock -> DeliverTx for each tx in the certificates -> EndBlock -> Commit event
+    /// loop. Certificates which were already executed (e.g. output again after a restart) are
+    /// skipped by the loader, and blocks without transactions are skipped if the policy says so.
+    async fn handle_block(&mut self, block: Vec<LoadedCertificate>) -> eyre::Result<()> {
+        if self.blocks.policy().skip_empty && block.iter().all(|loaded| loaded.txs() == 0) {
+            log::debug!("skipping empty block of {} certificates", block.len());
+            return Ok(());
+        }
+        let digests: Vec<_> = block
+            .iter()
+            .map(|loaded| loaded.certificate.digest())
+            .collect();
+
         // increment block
         let proposed_block_height = self.last_block_height + 1;
 
         // save it for next time
         self.last_block_height = proposed_block_height;
 
-        // drive the app through the event loop
-        self.begin_block(proposed_block_height)?;
-        self.reconstruct_and_deliver_txs(certificate)?;
-        self.end_block(proposed_block_height)?;
-        self.commit()?;
-        Ok(())
+        // log it before executing it, so that it gets replayed if anything crashes
+        self.log.append(proposed_block_height, &digests)?;
+
+        self.execute_block(proposed_block_height, block).await
     }
 
-    /// Handles ABCI queries coming to the primary and forwards them to the ABCI App. Each
-    /// handle call comes with a Sender channel which is used to send the response back to the
-    /// Primary and then to the client.
-    ///
-    /// Client => Primary => handle_cert => ABCI App => Primary => Client
-    fn handle_abci_query(
+    /// Drives the app through the event loop for the block at the provided height. The block
+    /// gets its metadata from its last certificate, e.g. the leader of a sub-DAG.
+    async fn execute_block(
         &mut self,
-        tx: OneShotSender<ResponseQuery>,
-        req: AbciQueryQuery,
+        height: i64,
+        block: Vec<LoadedCertificate>,
     ) -> eyre::Result<()> {
-        let req_height = req.height.unwrap_or(0);
-        let req_prove = req.prove.unwrap_or(false);
-
-        let resp = self.req_client.query(RequestQuery {
-            data: req.data.into(),
-            path: req.path,
-            height: req_height as i64,
-            prove: req_prove,
-        })?;
-
-        if let Err(err) = tx.send(resp) {
-            eyre::bail!("{:?}", err);
+        let last = match block.last() {
+            Some(last) => last.certificate.clone(),
+            None => eyre::bail!("block {} has no certificates", height),
+        };
+        let digests: Vec<_> = block
+            .iter()
+            .map(|loaded| loaded.certificate.digest())
+            .collect();
+        self.begin_block(height, &last).await?;
+        let batches = block
+            .into_iter()
+            .flat_map(|loaded| loaded.batches)
+            .collect();
+        let txs = self.deliver_batches(batches).await?;
+        let end_block = self.end_block(height).await?;
+        let commit = self.commit().await?;
+
+        let results = BlockResults::new(height, &digests, txs, end_block, commit);
+        self.handle_block_results(results)
+    }
+
+    /// Logs and persists what the app returned for a block, then publishes it.
+    fn handle_block_results(&mut self, results: BlockResults) -> eyre::Result<()> {
+        log::info!(
+            "committed block {} with {} txs ({} failed), app hash {}",
+            results.height,
+            results.txs.len(),
+            results.failed_txs(),
+            results.app_hash
+        );
+        for (i, tx) in results
+            .txs
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.code != 0)
+        {
+            log::debug!(
+                "tx {} of block {} failed with code {} ({}): {}",
+                i,
+                results.height,
+                tx.code,
+                tx.codespace,
+                tx.log
+            );
+        }
+
+        // the committee is fixed by Narwhal, so the app cannot change it
+        if !results.validator_updates.is_empty() {
+            log::warn!(
+                "ignoring {} validator updates at height {}",
+                results.validator_updates.len(),
+                results.height
+            );
+        }
+        if results.consensus_param_updates {
+            log::warn!(
+                "ignoring consensus param updates at height {}",
+                results.height
+            );
         }
+
+        // a replayed block must lead to the same state as the first time it was executed, the
+        // app has diverged otherwise and must not execute any further block
+        if let Some(previous) = self.log.results(results.height)? {
+            if previous.app_hash != results.app_hash {
+                eyre::bail!(
+                    "app hash mismatch at height {}: {} before, {} now",
+                    results.height,
+                    previous.app_hash,
+                    results.app_hash
+                );
+            }
+        }
+        self.log.put_results(&results)?;
+
+        let _ = self.tx_events.send(EngineEvent::Block(results));
         Ok(())
     }
 
-    /// Opens a RocksDB handle to a Worker's database and tries to read the batch
-    /// stored at the provided certificate's digest.
-    fn reconstruct_batch(&self, digest: Digest, worker_id: u32) -> eyre::Result<Vec<u8>> {
-        // Open the database to each worker
-        // TODO: Figure out if this is expensive
-        let db = rocksdb::DB::open_for_read_only(
-            &rocksdb::Options::default(),
-            self.worker_db(worker_id),
-            true,
-        )?;
-
-        // Query the db
-        let key = digest.to_vec();
-        match db.get(&key) {
-            Ok(Some(res)) => Ok(res),
-            Ok(None) => eyre::bail!("digest {} not found", digest),
-            Err(err) => eyre::bail!(err),
+    /// Re-executes the logged certificates above the app's last block height, reading them
+    /// from the Primary's store. This recovers the certificates that were output while the app
+    /// was down, or that it had not committed before crashing.
+    async fn replay(&mut self, loader: &mut BatchLoader) -> eyre::Result<()> {
+        let app_height = self.last_block_height;
+        let log_height = self.log.last_height()?;
+        if log_height <= app_height {
+            return Ok(());
         }
-    }
+        log::warn!(
+            "replaying certificates from height {} to {}",
+            app_height + 1,
+            log_height
+        );
 
-    /// Calls DeliverTx on the ABCI app
-    /// Deserializes a raw abtch as `WorkerMesssage::Batch` and proceeds to deliver
-    /// each transaction over the DeliverTx API.
-    fn deliver_batch(&mut self, batch: Vec<u8>) -> eyre::Result<()> {
-        // Deserialize and parse the message.
-        match bincode::deserialize(&batch) {
-            Ok(WorkerMessage::Batch(batch)) => {
-                batch.into_iter().try_for_each(|tx| {
-                    self.deliver_tx(tx)?;
-                    Ok::<_, eyre::Error>(())
-                })?;
+        let store = self.open_store()?;
+        for height in app_height + 1..=log_height {
+            let digests = self
+                .log
+                .digests(height)?
+                .ok_or_else(|| eyre::eyre!("no certificates logged at height {}", height))?;
+            let mut block = Vec::new();
+            for digest in digests {
+                let certificate = read_certificate(&store, &digest)?;
+                block.push(loader.load(certificate).await?);
             }
-            _ => eyre::bail!("unrecognized message format"),
-        };
+            self.execute_block(height, block).await?;
+        }
+        self.last_block_height = log_height;
+
         Ok(())
     }
 
-    /// Reconstructs the batch corresponding to the provided Primary's certificate from the Workers' stores
-    /// and proceeds to deliver each tx to the App over ABCI's DeliverTx endpoint.
-    fn reconstruct_and_deliver_txs(&mut self, certificate: Certificate) -> eyre::Result<()> {
-        // Try reconstructing the batches from the cert digests
-        //
-        // NB:
-        // This is maybe a false positive by Clippy, without the `collect` the Iterator fails
-        // iterator fails to compile because we're mutably borrowing in the `try_for_each`
-        // when we've already immutably borrowed in the `.map`.
-        #[allow(clippy::needless_collect)]
-        let batches = certificate
-            .header
-            .payload
-            .into_iter()
-            .map(|(digest, worker_id)| self.reconstruct_batch(digest, worker_id))
-            .collect::<Vec<_>>();
+    /// Rebuilds the blocks which were being built when the node stopped, from the pending
+    /// certificates of the log, and executes the ones they complete. Consensus does not output
+    /// these certificates again, so the blocks would not be the same as on the other nodes
+    /// otherwise.
+    async fn restore_pending(&mut self, loader: &mut BatchLoader) -> eyre::Result<()> {
+        let digests = self.log.pending()?;
+        if digests.is_empty() {
+            return Ok(());
+        }
+        log::warn!("restoring {} pending certificates", digests.len());
 
-        // Deliver
-        batches.into_iter().try_for_each(|batch| {
-            // this will throw an error if the deserialization failed anywhere
-            let batch = batch?;
-            self.deliver_batch(batch)?;
-            Ok::<_, eyre::Error>(())
-        })?;
+        let store = self.open_store()?;
+        for digest in digests {
+            let certificate = read_certificate(&store, &digest)?;
+            let loaded = loader.load(certificate).await?;
+            for block in self.blocks.push(loaded) {
+                self.handle_block(block).await?;
+            }
+        }
 
         Ok(())
     }
 
-    /// Helper function for getting the database handle to a worker associated
-    /// with a primary (e.g. Primary db-0 -> Worker-0 db-0-0, Wroekr-1 db-0-1 etc.)
-    fn worker_db(&self, id: u32) -> String {
-        format!("{}-{}", self.store_path, id)
+    /// Opens the Primary's store, in which the certificates are stored by digest.
+    fn open_store(&self) -> eyre::Result<rocksdb::DB> {
+        let store =
+            rocksdb::DB::open_for_read_only(&rocksdb::Options::default(), &self.store_path, false)?;
+        Ok(store)
+    }
+
+    /// Delivers each tx of the batches to the App over ABCI's DeliverTx endpoint. Returns the
+    /// app's responses, in order.
+    async fn deliver_batches(
+        &mut self,
+        batches: Vec<Batch>,
+    ) -> eyre::Result<Vec<ResponseDeliverTx>> {
+        let mut responses = Vec::new();
+        for tx in batches.into_iter().flatten() {
+            responses.push(self.deliver_tx(tx).await?);
+        }
+        Ok(responses)
     }
 }
 
 // Tendermint Lifecycle Helpers
 impl Engine {
-    /// Calls the `InitChain` hook on the app, ignores "already initialized" errors.
-    pub fn init_chain(&mut self) -> eyre::Result<()> {
-        let mut client = ClientBuilder::default().connect(&self.app_address)?;
-        match client.init_chain(RequestInitChain::default()) {
+    /// Calls the `InitChain` hook on the app with the genesis state, ignores "already
+    /// initialized" errors.
+    pub async fn init_chain(&mut self) -> eyre::Result<()> {
+        let mut client = AbciClient::connect(self.app_address).await?;
+        let req = RequestInitChain {
+            time: Some(Timestamp {
+                seconds: self.genesis_time as i64,
+                nanos: 0,
+            }),
+            app_state_bytes: self.genesis.clone(),
+            ..Default::default()
+        };
+        match client.init_chain(req).await {
             Ok(_) => {}
             Err(err) => {
                 // ignore errors about the chain being uninitialized
@@ -208,49 +343,72 @@ impl Engine {
                     log::warn!("{}", err);
                     return Ok(());
                 }
-                eyre::bail!(err)
+                return Err(err);
             }
         };
         Ok(())
     }
 
-    /// Calls the `BeginBlock` hook on the ABCI app. For now, it just makes a request with
-    /// the new block height.
-    // If we wanted to, we could add additional arguments to be forwarded from the Consensus
-    // to the App logic on the beginning of each block.
-    fn begin_block(&mut self, height: i64) -> eyre::Result<()> {
+    /// Calls the `BeginBlock` hook on the ABCI app with the new block height and the
+    /// certificate's metadata:
+    /// * its digest as the block `hash`,
+    /// * its author as the header's `proposer_address`,
+    /// * its round in the `last_commit_info`,
+    /// * a timestamp of `genesis_time + round` seconds, which all the nodes agree on since it
+    /// does not depend on their clocks.
+    async fn begin_block(&mut self, height: i64, certificate: &Certificate) -> eyre::Result<()> {
+        let round = certificate.header.round;
+        // certificates of older rounds can get committed after newer ones
+        let timestamp = std::cmp::max(self.genesis_time + round, self.last_timestamp);
+        self.last_timestamp = timestamp;
+
         let req = RequestBeginBlock {
+            hash: certificate.digest().to_vec(),
             header: Some(Header {
                 height,
+                time: Some(Timestamp {
+                    seconds: timestamp as i64,
+                    nanos: 0,
+                }),
+                proposer_address: certificate.header.author.0.to_vec(),
+                ..Default::default()
+            }),
+            last_commit_info: Some(LastCommitInfo {
+                round: round as i32,
                 ..Default::default()
             }),
             ..Default::default()
         };
 
-        self.client.begin_block(req)?;
+        self.client.begin_block(req).await?;
         Ok(())
     }
 
     /// Calls the `DeliverTx` hook on the ABCI app.
-    fn deliver_tx(&mut self, tx: Transaction) -> eyre::Result<()> {
-        self.client.deliver_tx(RequestDeliverTx { tx })?;
-        Ok(())
+    async fn deliver_tx(&mut self, tx: Transaction) -> eyre::Result<ResponseDeliverTx> {
+        self.client.deliver_tx(RequestDeliverTx { tx }).await
     }
 
     /// Calls the `EndBlock` hook on the ABCI app. For now, it just makes a request with
     /// the proposed block height.
     // If we wanted to, we could add additional arguments to be forwarded from the Consensus
     // to the App logic on the end of each block.
-    fn end_block(&mut self, height: i64) -> eyre::Result<()> {
+    async fn end_block(&mut self, height: i64) -> eyre::Result<ResponseEndBlock> {
         let req = RequestEndBlock { height };
-        self.client.end_block(req)?;
-        Ok(())
+        self.client.end_block(req).await
     }
 
     /// Calls the `Commit` hook on the ABCI app.
-    fn commit(&mut self) -> eyre::Result<()> {
-        self.client.commit()?;
-        Ok(())
+    async fn commit(&mut self) -> eyre::Result<ResponseCommit> {
+        self.client.commit().await
+    }
+}
+
+/// Reads the certificate with the provided digest from the Primary's store.
+fn read_certificate(store: &rocksdb::DB, digest: &Digest) -> eyre::Result<Certificate> {
+    match store.get(digest.to_vec())? {
+        Some(certificate) => Ok(bincode::deserialize(&certificate)?),
+        None => eyre::bail!("certificate {} not found", digest),
     }
 }
 
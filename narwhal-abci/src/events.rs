@@ -0,0 +1,152 @@
+use narwhal_crypto::Digest;
+use serde::{Deserialize, Serialize};
+use tendermint_proto::abci::{self, ResponseCommit, ResponseDeliverTx, ResponseEndBlock};
+use tendermint_proto::crypto::public_key::Sum;
+
+/// Events emitted by the Engine as it drives the app, which the ABCI API streams to its
+/// subscribers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineEvent {
+    /// A block was executed and committed by the app.
+    Block(BlockResults),
+}
+
+impl EngineEvent {
+    /// The name of the event, e.g. in server-sent events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EngineEvent::Block(_) => "block",
+        }
+    }
+}
+
+/// What the app returned while executing the block built from a group of certificates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockResults {
+    pub height: i64,
+    /// The hex-encoded digests of the certificates the block was built from, in order
+    pub certificates: Vec<String>,
+    /// The results of the block's transactions, in order
+    pub txs: Vec<TxResult>,
+    /// The validator set changes requested by the app in `EndBlock`
+    pub validator_updates: Vec<ValidatorUpdate>,
+    /// Whether the app requested to change the consensus parameters in `EndBlock`
+    pub consensus_param_updates: bool,
+    /// The events of `EndBlock`
+    pub events: Vec<Event>,
+    /// The hex-encoded app hash returned by `Commit`
+    pub app_hash: String,
+}
+
+impl BlockResults {
+    pub fn new(
+        height: i64,
+        certificates: &[Digest],
+        txs: Vec<ResponseDeliverTx>,
+        end_block: ResponseEndBlock,
+        commit: ResponseCommit,
+    ) -> Self {
+        Self {
+            height,
+            certificates: certificates
+                .iter()
+                .map(|digest| hex::encode(digest.to_vec()))
+                .collect(),
+            txs: txs.into_iter().map(Into::into).collect(),
+            validator_updates: end_block
+                .validator_updates
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            consensus_param_updates: end_block.consensus_param_updates.is_some(),
+            events: end_block.events.into_iter().map(Into::into).collect(),
+            app_hash: hex::encode(commit.data),
+        }
+    }
+
+    /// The number of transactions which the app executed with a non-zero code.
+    pub fn failed_txs(&self) -> usize {
+        self.txs.iter().filter(|tx| tx.code != 0).count()
+    }
+}
+
+/// The `DeliverTx` response of a transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TxResult {
+    pub code: u32,
+    /// The hex-encoded data returned by the app
+    pub data: String,
+    pub log: String,
+    pub codespace: String,
+    pub gas_wanted: i64,
+    pub gas_used: i64,
+    pub events: Vec<Event>,
+}
+
+impl From<ResponseDeliverTx> for TxResult {
+    fn from(res: ResponseDeliverTx) -> Self {
+        Self {
+            code: res.code,
+            data: hex::encode(res.data),
+            log: res.log,
+            codespace: res.codespace,
+            gas_wanted: res.gas_wanted,
+            gas_used: res.gas_used,
+            events: res.events.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// An ABCI event, whose attributes are decoded as UTF-8.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub attributes: Vec<EventAttribute>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EventAttribute {
+    pub key: String,
+    pub value: String,
+    pub index: bool,
+}
+
+impl From<abci::Event> for Event {
+    fn from(event: abci::Event) -> Self {
+        Self {
+            kind: event.r#type,
+            attributes: event
+                .attributes
+                .into_iter()
+                .map(|attr| EventAttribute {
+                    key: String::from_utf8_lossy(&attr.key).into_owned(),
+                    value: String::from_utf8_lossy(&attr.value).into_owned(),
+                    index: attr.index,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A validator set change requested by the app.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorUpdate {
+    /// The hex-encoded public key, empty if the app did not set it
+    pub pub_key: String,
+    pub power: i64,
+}
+
+impl From<abci::ValidatorUpdate> for ValidatorUpdate {
+    fn from(update: abci::ValidatorUpdate) -> Self {
+        let pub_key = match update.pub_key.and_then(|key| key.sum) {
+            Some(Sum::Ed25519(key)) | Some(Sum::Secp256k1(key)) => hex::encode(key),
+            None => String::new(),
+        };
+        Self {
+            pub_key,
+            power: update.power,
+        }
+    }
+}
@@ -1,9 +1,33 @@
 mod abci_server;
 pub use abci_server::AbciApi;
 
+mod abci_client;
+pub use abci_client::AbciClient;
+
 mod engine;
 pub use engine::Engine;
 
+mod requests;
+pub use requests::{serve_check_txs, serve_queries};
+
+mod sync;
+pub use sync::BatchSync;
+
+mod worker_store;
+pub use worker_store::WorkerStores;
+
+mod loader;
+pub use loader::{BatchLoader, LoadedCertificate};
+
+mod blocks;
+pub use blocks::{BlockBoundary, BlockBuilder, BlockPolicy};
+
+mod cert_log;
+pub use cert_log::CertificateLog;
+
+mod events;
+pub use events::{BlockResults, EngineEvent, Event, EventAttribute, TxResult, ValidatorUpdate};
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,6 +35,26 @@ pub struct BroadcastTxQuery {
     tx: String,
 }
 
+/// The result of a `broadcast_tx` call. A non-zero `code` means that the transaction was
+/// rejected and not forwarded to the workers.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BroadcastTxResponse {
+    pub code: u32,
+    pub log: String,
+    pub codespace: String,
+}
+
+impl BroadcastTxResponse {
+    /// Errors which happened in the node, before or after reaching the app.
+    fn error(err: impl std::fmt::Display) -> Self {
+        Self {
+            code: 1,
+            log: err.to_string(),
+            codespace: "narwhal".to_string(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AbciQueryQuery {
     path: String,
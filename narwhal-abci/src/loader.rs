@@ -0,0 +1,149 @@
+use crate::engine::{Batch, WorkerMessage};
+use crate::{BatchSync, CertificateLog, WorkerStores};
+use narwhal_crypto::{Digest, Hash, PublicKey};
+use narwhal_primary::Certificate;
+use std::time::Instant;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// A certificate whose batches were read from the workers' stores and decoded, so that its
+/// transactions are ready to be delivered to the app.
+pub struct LoadedCertificate {
+    pub certificate: Certificate,
+    /// The certificate's batches, in the order of its payload
+    pub batches: Vec<Batch>,
+}
+
+impl LoadedCertificate {
+    /// The number of transactions in the certificate's batches.
+    pub fn txs(&self) -> usize {
+        self.batches.iter().map(Vec::len).sum()
+    }
+}
+
+/// Loads the batches of the certificates output by consensus ahead of the Engine, so that
+/// reading and decoding them overlaps with the delivery of the previous certificates to the app.
+pub struct BatchLoader {
+    stores: WorkerStores,
+    batch_sync: BatchSync,
+}
+
+impl BatchLoader {
+    pub fn new(store_path: &str, batch_sync: BatchSync) -> Self {
+        Self {
+            stores: WorkerStores::new(store_path),
+            batch_sync,
+        }
+    }
+
+    /// Loads the certificates received from consensus one after the other, and sends them to
+    /// the Engine in the same order. The channel to the Engine is bounded, so the loader stays
+    /// at most its capacity certificates ahead. Certificates which were already executed or
+    /// restored as pending (e.g. output again after a restart) are skipped, since their batches
+    /// may be gone from the workers' stores. Stops after the first certificate which cannot be
+    /// loaded.
+    pub fn spawn(
+        mut self,
+        log: CertificateLog,
+        mut rx_output: Receiver<Certificate>,
+        tx_loaded: Sender<eyre::Result<LoadedCertificate>>,
+    ) {
+        tokio::spawn(async move {
+            while let Some(certificate) = rx_output.recv().await {
+                let digest = certificate.digest();
+                let loaded = match log.height(&digest) {
+                    Ok(Some(height)) => {
+                        log::debug!(
+                            "skipping certificate {} executed at height {}",
+                            digest,
+                            height
+                        );
+                        continue;
+                    }
+                    Ok(None) => match log.pending() {
+                        Ok(pending) if pending.contains(&digest) => {
+                            log::debug!("skipping pending certificate {}", digest);
+                            continue;
+                        }
+                        Ok(_) => self.load(certificate).await,
+                        Err(err) => Err(err),
+                    },
+                    Err(err) => Err(err),
+                };
+                let failed = loaded.is_err();
+                if tx_loaded.send(loaded).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Reconstructs the batches corresponding to the provided Primary's certificate from the
+    /// Workers' stores, and decodes them.
+    pub async fn load(&mut self, certificate: Certificate) -> eyre::Result<LoadedCertificate> {
+        let author = certificate.header.author;
+        let mut batches = Vec::new();
+        for (digest, worker_id) in &certificate.header.payload {
+            let batch = self.reconstruct_batch(digest, *worker_id, author).await?;
+            batches.push(decode_batch(&batch)?);
+        }
+        Ok(LoadedCertificate {
+            certificate,
+            batches,
+        })
+    }
+
+    /// Reads the batch stored at the provided digest in the Worker's database. If the Worker
+    /// has not stored it yet, asks it to synchronize the batch from the certificate's author,
+    /// and polls its database with an exponential backoff until the batch sync timeout.
+    async fn reconstruct_batch(
+        &mut self,
+        digest: &Digest,
+        worker_id: u32,
+        author: PublicKey,
+    ) -> eyre::Result<Vec<u8>> {
+        let start = Instant::now();
+        let mut delay = self.batch_sync.retry_delay;
+        loop {
+            match self.stores.read(worker_id, digest) {
+                Ok(Some(batch)) => return Ok(batch),
+                Ok(None) => {}
+                // e.g. the worker did not create its database yet
+                Err(err) => log::warn!("could not read worker {} store: {}", worker_id, err),
+            }
+
+            if start.elapsed() >= self.batch_sync.timeout {
+                eyre::bail!(
+                    "batch {} not found in worker {} after {:?}",
+                    digest,
+                    worker_id,
+                    self.batch_sync.timeout
+                );
+            }
+            log::warn!(
+                "batch {} not found in worker {}, requesting it and retrying in {:?}",
+                digest,
+                worker_id,
+                delay
+            );
+            // the worker may already be synchronizing it, so failing to ask is not fatal
+            if let Err(err) = self
+                .batch_sync
+                .request(worker_id, vec![digest.clone()], author)
+                .await
+            {
+                log::warn!("could not request batch {}: {}", digest, err);
+            }
+
+            tokio::time::sleep(delay).await;
+            delay = self.batch_sync.next_delay(delay);
+        }
+    }
+}
+
+/// Deserializes a raw batch as `WorkerMessage::Batch`.
+fn decode_batch(batch: &[u8]) -> eyre::Result<Batch> {
+    match bincode::deserialize(batch) {
+        Ok(WorkerMessage::Batch(batch)) => Ok(batch),
+        _ => eyre::bail!("unrecognized message format"),
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{AbciClient, AbciQueryQuery};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tendermint_proto::abci::{RequestCheckTx, RequestQuery, ResponseCheckTx, ResponseQuery};
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot::Sender as OneShotSender;
+use tokio::sync::Mutex;
+
+/// The code of the responses to the requests which could not reach the app.
+const ERROR_CODE: u32 = 1;
+
+type QueryReceiver = Receiver<(OneShotSender<ResponseQuery>, AbciQueryQuery)>;
+
+/// Forwards the queries received by the Primary's ABCI Server API to the ABCI App, over a pool
+/// of `concurrency` connections of their own, each served by its own task. Up to `concurrency`
+/// queries are answered at once, and they neither wait for the blocks nor delay them, since the
+/// Engine executes them on its own connection. Each query comes with a Sender channel which is
+/// used to send the response back to the Primary and then to the client.
+///
+/// Client => Primary => serve_queries => ABCI App => Primary => Client
+pub async fn serve_queries(
+    app_address: SocketAddr,
+    rx_abci_queries: QueryReceiver,
+    concurrency: usize,
+) -> eyre::Result<()> {
+    let rx_abci_queries = Arc::new(Mutex::new(rx_abci_queries));
+    let mut connections = Vec::new();
+    for _ in 0..concurrency.max(1) {
+        let client = AbciClient::connect(app_address).await?;
+        connections.push(tokio::spawn(serve_connection(
+            app_address,
+            client,
+            rx_abci_queries.clone(),
+        )));
+    }
+    for connection in connections {
+        connection.await?;
+    }
+    Ok(())
+}
+
+/// Forwards the queries to the app over one connection of the pool, one at a time. A query
+/// which fails, e.g. because the app dropped the connection, gets an error response, and the
+/// connection is re-established for the next one.
+async fn serve_connection(
+    app_address: SocketAddr,
+    client: AbciClient,
+    rx_abci_queries: Arc<Mutex<QueryReceiver>>,
+) {
+    let mut client = Some(client);
+    loop {
+        // the idle connections take turns waiting for the next query
+        let next = rx_abci_queries.lock().await.recv().await;
+        let (tx, req) = match next {
+            Some(next) => next,
+            None => return,
+        };
+
+        let req = RequestQuery {
+            data: req.data.into(),
+            path: req.path,
+            height: req.height.unwrap_or(0) as i64,
+            prove: req.prove.unwrap_or(false),
+        };
+        let resp = match connection(app_address, &mut client).await {
+            Ok(connection) => connection.query(req).await,
+            Err(err) => Err(err),
+        };
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(err) => {
+                // the connection may be left mid-response, so the next query gets a new one
+                client = None;
+                log::error!("could not query the app: {:?}", err);
+                ResponseQuery {
+                    code: ERROR_CODE,
+                    log: err.to_string(),
+                    value: err.to_string().into(),
+                    ..Default::default()
+                }
+            }
+        };
+
+        if tx.send(resp).is_err() {
+            log::debug!("the client stopped waiting for its query");
+        }
+    }
+}
+
+/// Forwards the transactions received by the Primary's `broadcast_tx` endpoint to the ABCI App
+/// over a connection of their own, to be validated via CheckTx before they get forwarded to
+/// the workers. Like the queries, a failed check is rejected and the connection re-established.
+pub async fn serve_check_txs(
+    app_address: SocketAddr,
+    mut rx_abci_check_txs: Receiver<(OneShotSender<ResponseCheckTx>, Vec<u8>)>,
+) -> eyre::Result<()> {
+    let mut client = Some(AbciClient::connect(app_address).await?);
+    while let Some((tx, req)) = rx_abci_check_txs.recv().await {
+        let req = RequestCheckTx {
+            tx: req,
+            ..Default::default()
+        };
+        let resp = match connection(app_address, &mut client).await {
+            Ok(connection) => connection.check_tx(req).await,
+            Err(err) => Err(err),
+        };
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(err) => {
+                client = None;
+                log::error!("could not check the transaction with the app: {:?}", err);
+                ResponseCheckTx {
+                    code: ERROR_CODE,
+                    log: err.to_string(),
+                    ..Default::default()
+                }
+            }
+        };
+
+        if tx.send(resp).is_err() {
+            log::debug!("the client stopped waiting for its transaction check");
+        }
+    }
+    Ok(())
+}
+
+/// The connection to the app, re-established if the previous request dropped it.
+async fn connection(
+    app_address: SocketAddr,
+    client: &mut Option<AbciClient>,
+) -> eyre::Result<&mut AbciClient> {
+    if client.is_none() {
+        *client = Some(AbciClient::connect(app_address).await?);
+    }
+    Ok(client.as_mut().unwrap())
+}
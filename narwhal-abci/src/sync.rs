@@ -0,0 +1,61 @@
+use futures::SinkExt;
+use narwhal_crypto::{Digest, PublicKey};
+use narwhal_primary::PrimaryWorkerMessage;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// How the Engine gets the batches of a certificate which its workers have not stored yet, e.g.
+/// because they lag behind the primary. The Engine asks its worker to synchronize the batches
+/// from the certificate author's worker, like the primary does for the headers it votes on, and
+/// polls the worker's store until they show up.
+#[derive(Debug, Clone)]
+pub struct BatchSync {
+    /// The addresses on which this node's workers receive the primary's messages, by worker id
+    pub workers: HashMap<u32, SocketAddr>,
+    /// The delay before the first retry, doubled after each retry
+    pub retry_delay: Duration,
+    /// The maximum delay between two retries
+    pub max_retry_delay: Duration,
+    /// How long to wait for a batch before giving up on the certificate
+    pub timeout: Duration,
+}
+
+impl Default for BatchSync {
+    fn default() -> Self {
+        Self {
+            workers: HashMap::new(),
+            retry_delay: Duration::from_millis(100),
+            max_retry_delay: Duration::from_secs(5),
+            timeout: Duration::from_secs(600),
+        }
+    }
+}
+
+impl BatchSync {
+    /// Asks the worker to fetch the batches from the worker with the same id of `author`.
+    pub async fn request(
+        &self,
+        worker_id: u32,
+        digests: Vec<Digest>,
+        author: PublicKey,
+    ) -> eyre::Result<()> {
+        let address = self
+            .workers
+            .get(&worker_id)
+            .ok_or_else(|| eyre::eyre!("unknown worker {}", worker_id))?;
+        let message = bincode::serialize(&PrimaryWorkerMessage::Synchronize(digests, author))?;
+
+        let stream = TcpStream::connect(address).await?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport.send(message.into()).await?;
+        Ok(())
+    }
+
+    /// The delay to wait after `delay` before retrying again.
+    pub fn next_delay(&self, delay: Duration) -> Duration {
+        std::cmp::min(delay * 2, self.max_retry_delay)
+    }
+}
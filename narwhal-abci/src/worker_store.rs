@@ -0,0 +1,58 @@
+use eyre::WrapErr;
+use narwhal_crypto::Digest;
+use rocksdb::{Options, DB};
+use std::collections::HashMap;
+
+/// Long-lived read handles to the databases of the Primary's workers, which store the batches
+/// referenced by the certificates. Each worker's database is opened once, as a RocksDB secondary
+/// instance: unlike a read-only handle, it can catch up with the batches the worker wrote after
+/// it was opened, so it does not need to be opened again for every batch.
+pub struct WorkerStores {
+    /// The path to the Primary's store, which prefixes the workers' ones
+    store_path: String,
+    handles: HashMap<u32, DB>,
+}
+
+impl WorkerStores {
+    pub fn new(store_path: &str) -> Self {
+        Self {
+            store_path: store_path.to_string(),
+            handles: HashMap::new(),
+        }
+    }
+
+    /// The path to the database of a worker associated with the primary (e.g. Primary db-0 ->
+    /// Worker-0 db-0-0, Worker-1 db-0-1 etc.)
+    pub fn worker_path(&self, id: u32) -> String {
+        format!("{}-{}", self.store_path, id)
+    }
+
+    /// Reads the batch stored at the provided digest in the worker's database. If it is not
+    /// there, catches up with the worker's latest writes and reads it again.
+    pub fn read(&mut self, worker_id: u32, digest: &Digest) -> eyre::Result<Option<Vec<u8>>> {
+        let db = self.handle(worker_id)?;
+        let key = digest.to_vec();
+        if let Some(batch) = db.get(&key)? {
+            return Ok(Some(batch));
+        }
+        db.try_catch_up_with_primary()?;
+        Ok(db.get(&key)?)
+    }
+
+    /// The handle to the worker's database, opened on first use. Fails if the worker did not
+    /// create its database yet, in which case opening it is attempted again on the next read.
+    fn handle(&mut self, id: u32) -> eyre::Result<&DB> {
+        if !self.handles.contains_key(&id) {
+            let path = self.worker_path(id);
+            // secondary instances keep their own logs, apart from the worker's
+            let secondary_path = format!("{}-abci-secondary", path);
+            let mut options = Options::default();
+            // required by secondary instances
+            options.set_max_open_files(-1);
+            let db = DB::open_as_secondary(&options, &path, &secondary_path)
+                .wrap_err(format!("could not open worker store at {}", path))?;
+            self.handles.insert(id, db);
+        }
+        Ok(&self.handles[&id])
+    }
+}
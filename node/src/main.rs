@@ -1,6 +1,7 @@
 use crypto::PublicKey;
 use eyre::{Result, WrapErr};
 use std::net::SocketAddr;
+use std::time::Duration;
 
 // Copyright(C) Facebook, Inc. and its affiliates.
 use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, SubCommand};
@@ -11,14 +12,25 @@ use consensus::Consensus;
 use env_logger::Env;
 use primary::Primary;
 use store::Store;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task::JoinHandle;
 use worker::Worker;
 
-use narwhal_abci::{AbciApi, Engine};
+use narwhal_abci::{
+    serve_check_txs, serve_queries, AbciApi, BatchSync, BlockBoundary, BlockBuilder, BlockPolicy,
+    Engine,
+};
 
 /// The default channel capacity.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
+/// The default number of certificates whose batches are loaded ahead of their execution.
+pub const DEFAULT_PREFETCH: usize = 16;
+
+/// The default number of queries forwarded to the app at once.
+pub const DEFAULT_QUERY_CONCURRENCY: usize = 4;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches = App::new(crate_name!())
@@ -45,6 +57,30 @@ async fn main() -> Result<()> {
                         )
                         .args_from_usage(
                             "--abci-api=<URL> 'The address to receive ABCI connections to'",
+                        )
+                        .args_from_usage(
+                            "--genesis=[FILE] 'The file containing the genesis state of the app'",
+                        )
+                        .args_from_usage(
+                            "--genesis-time=[SECONDS] 'The UNIX time of the first round, which must be the same on all the nodes (defaults to the genesis timestamp, one of them is required)'",
+                        )
+                        .args_from_usage(
+                            "--batch-timeout=[SECONDS] 'How long to wait for the workers to synchronize the batches of a certificate (defaults to 600)'",
+                        )
+                        .args_from_usage(
+                            "--prefetch=[INT] 'How many certificates to load the batches of ahead of their execution (defaults to 16)'",
+                        )
+                        .args_from_usage(
+                            "--query-concurrency=[INT] 'How many queries to forward to the app at once, each over its own connection (defaults to 4)'",
+                        )
+                        .args_from_usage(
+                            "--block-boundary=[BOUNDARY] 'Where blocks end, which must be the same on all the nodes: certificate, sub-dag or round (defaults to certificate). sub-dag and round end blocks at every elected leader, so a skipped leader output within a later sub-DAG splits it'",
+                        )
+                        .args_from_usage(
+                            "--max-block-txs=[INT] 'End blocks early once they hold this many transactions'",
+                        )
+                        .args_from_usage(
+                            "--skip-empty-blocks 'Do not execute the blocks without transactions'",
                         ),
                 )
                 .subcommand(
@@ -116,6 +152,51 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
 
             let app_api = sub_matches.value_of("app-api").unwrap().to_string();
             let abci_api = sub_matches.value_of("abci-api").unwrap().to_string();
+            let genesis = match sub_matches.value_of("genesis") {
+                Some(filename) => std::fs::read(filename).context("Failed to load the genesis")?,
+                None => Vec::new(),
+            };
+            let genesis_time = match sub_matches.value_of("genesis-time") {
+                Some(time) => time
+                    .parse::<u64>()
+                    .context("The genesis time must be a UNIX timestamp in seconds")?,
+                None => genesis_timestamp(&genesis)?.ok_or_else(|| {
+                    eyre::eyre!(
+                        "The genesis time must be set by --genesis-time or the genesis timestamp"
+                    )
+                })?,
+            };
+            let mut batch_sync = batch_sync(&committee, &keypair_name, &parameters);
+            if let Some(timeout) = sub_matches.value_of("batch-timeout") {
+                let timeout = timeout
+                    .parse::<u64>()
+                    .context("The batch timeout must be a number of seconds")?;
+                batch_sync.timeout = Duration::from_secs(timeout);
+            }
+            let prefetch = match sub_matches.value_of("prefetch") {
+                Some(prefetch) => prefetch
+                    .parse::<usize>()
+                    .context("The prefetch must be a positive integer")?,
+                None => DEFAULT_PREFETCH,
+            };
+            let query_concurrency = match sub_matches.value_of("query-concurrency") {
+                Some(concurrency) => concurrency
+                    .parse::<usize>()
+                    .context("The query concurrency must be a positive integer")?,
+                None => DEFAULT_QUERY_CONCURRENCY,
+            };
+            let block_policy = BlockPolicy {
+                boundary: match sub_matches.value_of("block-boundary") {
+                    Some(boundary) => boundary.parse::<BlockBoundary>()?,
+                    None => BlockBoundary::Certificate,
+                },
+                max_txs: sub_matches
+                    .value_of("max-block-txs")
+                    .map(|max_txs| max_txs.parse::<usize>())
+                    .transpose()
+                    .context("The maximum block size must be a positive integer")?,
+                skip_empty: sub_matches.is_present("skip-empty-blocks"),
+            };
 
             Primary::spawn(
                 keypair,
@@ -140,6 +221,12 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
                 committee,
                 abci_api,
                 app_api,
+                genesis,
+                genesis_time,
+                batch_sync,
+                prefetch,
+                query_concurrency,
+                block_policy,
             )
             .await?;
         }
@@ -171,6 +258,7 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     unreachable!();
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn process(
     rx_output: Receiver<primary::Certificate>,
     store_path: &str,
@@ -178,6 +266,12 @@ async fn process(
     committee: Committee,
     abci_api: String,
     app_api: String,
+    genesis: Vec<u8>,
+    genesis_time: u64,
+    batch_sync: BatchSync,
+    prefetch: usize,
+    query_concurrency: usize,
+    block_policy: BlockPolicy,
 ) -> eyre::Result<()> {
     // address of mempool
     let mempool_address = committee
@@ -187,9 +281,19 @@ async fn process(
 
     // ABCI queries will be sent using this from the RPC to the ABCI client
     let (tx_abci_queries, rx_abci_queries) = channel(CHANNEL_CAPACITY);
+    // Transactions will be checked by the ABCI app before being sent to the mempool
+    let (tx_abci_check_txs, rx_abci_check_txs) = channel(CHANNEL_CAPACITY);
+    // The engine publishes the results of each block, which the RPC streams to its subscribers
+    let (tx_events, _) = broadcast::channel(CHANNEL_CAPACITY);
 
+    let api_events = tx_events.clone();
     tokio::spawn(async move {
-        let api = AbciApi::new(mempool_address, tx_abci_queries);
+        let api = AbciApi::new(
+            mempool_address,
+            tx_abci_queries,
+            tx_abci_check_txs,
+            api_events,
+        );
         // let tx_abci_queries = tx_abci_queries.clone();
         // Spawn the ABCI RPC endpoint
         let mut address = abci_api.parse::<SocketAddr>().unwrap();
@@ -201,8 +305,77 @@ async fn process(
     // Spawn the network receiver listening to messages from the other primaries.
     let mut app_address = app_api.parse::<SocketAddr>().unwrap();
     app_address.set_ip("0.0.0.0".parse().unwrap());
-    let mut engine = Engine::new(app_address, store_path, rx_abci_queries);
-    engine.run(rx_output).await?;
+    let mut engine = Engine::new(
+        app_address,
+        store_path,
+        genesis,
+        genesis_time,
+        tx_events,
+        batch_sync,
+        prefetch,
+        BlockBuilder::new(
+            block_policy,
+            committee.authorities.keys().cloned().collect(),
+        ),
+    )
+    .await?;
+    // the engine, the queries and the checks run in tasks of their own, so that the queries do
+    // not share the engine's task
+    let engine = tokio::spawn(async move { engine.run(rx_output).await });
+    let queries = tokio::spawn(serve_queries(
+        app_address,
+        rx_abci_queries,
+        query_concurrency,
+    ));
+    let check_txs = tokio::spawn(serve_check_txs(app_address, rx_abci_check_txs));
+    tokio::try_join!(join(engine), join(queries), join(check_txs))?;
 
     Ok(())
 }
+
+/// Waits for a task, failing if it failed or panicked.
+async fn join(task: JoinHandle<Result<()>>) -> Result<()> {
+    task.await?
+}
+
+/// Lets the Engine request missing batches from this node's workers, retrying as often as the
+/// primary does when it synchronizes headers.
+fn batch_sync(committee: &Committee, name: &PublicKey, parameters: &Parameters) -> BatchSync {
+    let workers = committee
+        .authorities
+        .get(name)
+        .map(|authority| {
+            authority
+                .workers
+                .iter()
+                .map(|(id, addresses)| (*id, addresses.primary_to_worker))
+                .collect()
+        })
+        .unwrap_or_default();
+    BatchSync {
+        workers,
+        retry_delay: Duration::from_millis(parameters.sync_retry_delay),
+        ..Default::default()
+    }
+}
+
+/// Reads the optional `timestamp` of a genesis file, as a number or a hex string.
+fn genesis_timestamp(genesis: &[u8]) -> Result<Option<u64>> {
+    if genesis.is_empty() {
+        return Ok(None);
+    }
+    let genesis: serde_json::Value =
+        serde_json::from_slice(genesis).context("The genesis must be a JSON file")?;
+    let timestamp = match &genesis["timestamp"] {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Number(timestamp) => timestamp.as_u64(),
+        serde_json::Value::String(timestamp) => match timestamp.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => timestamp.parse().ok(),
+        },
+        _ => None,
+    };
+    timestamp
+        .map(Some)
+        .ok_or_else(|| eyre::eyre!("Invalid genesis timestamp {}", genesis["timestamp"]))
+}